@@ -2,16 +2,53 @@ use anyhow::*;
 use fs_extra::copy_items;
 use fs_extra::dir::CopyOptions;
 use std::env;
+use std::fmt::Write as _;
+use std::path::Path;
 
 fn main() -> Result<()> {
 
-    println!("cargo:rerun-if-changed=res/*");
+    println!("cargo:rerun-if-changed=res");
 
     let out_dir = env::var("OUT_DIR")?;
     let mut copy_options = CopyOptions::new();
     copy_options.overwrite = true;
-    let mut paths_to_copy = Vec::new();
-    paths_to_copy.push("res/");
-    copy_items(&paths_to_copy, out_dir, &copy_options)?;
+    let paths_to_copy = vec!["res/"];
+    copy_items(&paths_to_copy, &out_dir, &copy_options)?;
+
+    if env::var_os("CARGO_FEATURE_EMBED_ASSETS").is_some() {
+        write_bundle_index(Path::new(&out_dir).join("embedded_assets.rs"))?;
+    }
+    Ok(())
+}
+
+/// Writes the table `src/embedded_assets.rs` includes: every file below
+/// `res/` by its path relative to it, sorted for binary search.
+fn write_bundle_index(path: impl AsRef<Path>) -> Result<()> {
+    let mut names = Vec::new();
+    for entry in glob::glob("res/**/*")? {
+        let entry = entry?;
+        if !entry.is_file() {
+            continue;
+        }
+        let relative = entry.strip_prefix("res")?;
+        let name = relative
+            .components()
+            .map(|component| component.as_os_str().to_str().context("asset paths must be UTF-8"))
+            .collect::<Result<Vec<_>>>()?
+            .join("/");
+        names.push(name);
+    }
+    names.sort();
+
+    let mut index = String::from("static ASSETS: &[(&str, &[u8])] = &[\n");
+    for name in &names {
+        writeln!(
+            index,
+            "    ({:?}, include_bytes!(concat!(env!(\"CARGO_MANIFEST_DIR\"), \"/res/\", {:?}))),",
+            name, name
+        )?;
+    }
+    index.push_str("];\n");
+    std::fs::write(path, index)?;
     Ok(())
 }
@@ -0,0 +1,204 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+
+use crate::model::Model;
+use crate::resources::{self, LoadOptions};
+use crate::{texture, vfs};
+
+/// Shared access to a loaded asset. Cloning only bumps a reference count, and
+/// the asset, GPU resources included, is freed with its last handle.
+pub struct Handle<T>(Arc<T>);
+
+impl<T> Handle<T> {
+    /// A handle to an asset no cache knows about.
+    pub fn new(asset: T) -> Self {
+        Self(Arc::new(asset))
+    }
+
+    /// Whether both handles refer to the same asset.
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        Arc::ptr_eq(&a.0, &b.0)
+    }
+
+    /// How many handles to the asset exist, this one included.
+    pub fn count(handle: &Self) -> usize {
+        Arc::strong_count(&handle.0)
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> std::ops::Deref for Handle<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// GPU memory taken up by assets, in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GpuMemory {
+    pub textures: u64,
+    pub meshes: u64,
+}
+
+impl GpuMemory {
+    pub fn total(&self) -> u64 {
+        self.textures + self.meshes
+    }
+}
+
+/// Loads models and textures once and hands out [`Handle`]s to them.
+///
+/// Loading the same file again while a handle to it is still around gives
+/// that asset instead of a new copy, and materials naming the same image
+/// share one texture, across models too. The cache itself only keeps weak
+/// references, so an asset nobody holds a handle to is freed right away.
+#[derive(Default)]
+pub struct AssetCache {
+    // Keyed by normalized file name and whether it's a normal map, which
+    // changes the texture's format
+    textures: HashMap<(String, bool), Weak<texture::Texture>>,
+    // Stand-in for material maps a model doesn't have
+    white: Weak<texture::Texture>,
+    models: HashMap<(String, LoadOptions), Weak<Model>>,
+}
+
+impl AssetCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// See [`resources::load_texture`].
+    pub async fn load_texture(
+        &mut self,
+        file_name: &str,
+        is_normal_map: bool,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<Handle<texture::Texture>> {
+        if let Some(texture) = self.cached_texture(file_name, is_normal_map) {
+            return Ok(texture);
+        }
+        let texture = resources::load_texture(file_name, is_normal_map, device, queue).await?;
+        Ok(self.insert_texture(file_name, is_normal_map, texture))
+    }
+
+    pub(crate) fn cached_texture(&mut self, file_name: &str, is_normal_map: bool) -> Option<Handle<texture::Texture>> {
+        self.purge();
+        self.textures.get(&(key(file_name), is_normal_map)).and_then(Weak::upgrade).map(Handle)
+    }
+
+    pub(crate) fn insert_texture(
+        &mut self,
+        file_name: &str,
+        is_normal_map: bool,
+        texture: texture::Texture,
+    ) -> Handle<texture::Texture> {
+        let handle = Handle::new(texture);
+        self.textures.insert((key(file_name), is_normal_map), Arc::downgrade(&handle.0));
+        handle
+    }
+
+    /// A 1x1 white texture holding linear data, shared by every material
+    /// without a metallic-roughness map.
+    pub(crate) fn white_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<Handle<texture::Texture>> {
+        if let Some(texture) = self.white.upgrade() {
+            return Ok(Handle(texture));
+        }
+        let handle = Handle::new(texture::Texture::from_color(device, queue, [255; 4], "White", true)?);
+        self.white = Arc::downgrade(&handle.0);
+        Ok(handle)
+    }
+
+    /// See [`resources::load_model`].
+    pub async fn load_model(
+        &mut self,
+        file_name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Handle<Model>> {
+        self.load_model_with_options(file_name, device, queue, layout, &LoadOptions::default()).await
+    }
+
+    /// See [`resources::load_model_with_options`]. The same file loaded with
+    /// different options gives different models.
+    pub async fn load_model_with_options(
+        &mut self,
+        file_name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        options: &LoadOptions,
+    ) -> anyhow::Result<Handle<Model>> {
+        self.purge();
+        let model_key = (key(file_name), *options);
+        if let Some(model) = self.models.get(&model_key).and_then(Weak::upgrade) {
+            return Ok(Handle(model));
+        }
+        let model = resources::load_model_cached(file_name, device, queue, layout, options, self).await?;
+        let handle = Handle::new(model);
+        self.models.insert(model_key, Arc::downgrade(&handle.0));
+        Ok(handle)
+    }
+
+    /// Forgets assets whose last handle is gone. Their memory was already
+    /// freed then; this just keeps the lookup tables small, and happens on
+    /// every load anyway.
+    pub fn purge(&mut self) {
+        self.textures.retain(|_, texture| texture.strong_count() > 0);
+        self.models.retain(|_, model| model.strong_count() > 0);
+    }
+
+    /// Textures loaded from files that are still in use.
+    pub fn texture_count(&self) -> usize {
+        self.textures.values().filter(|texture| texture.strong_count() > 0).count()
+    }
+
+    /// Models that are still in use.
+    pub fn model_count(&self) -> usize {
+        self.models.values().filter(|model| model.strong_count() > 0).count()
+    }
+
+    /// GPU memory of the assets still in use, counting each shared texture
+    /// once. Includes the textures models made themselves, like single color
+    /// ones for materials without an image.
+    pub fn gpu_memory(&self) -> GpuMemory {
+        let mut memory = GpuMemory::default();
+        let mut counted = HashSet::new();
+        let mut count_texture = |texture: &texture::Texture| {
+            if counted.insert(texture as *const texture::Texture) {
+                memory.textures += texture.gpu_bytes();
+            }
+        };
+        let textures = self.textures.values().filter_map(Weak::upgrade).collect::<Vec<_>>();
+        for texture in &textures {
+            count_texture(texture);
+        }
+        let models = self.models.values().filter_map(Weak::upgrade).collect::<Vec<_>>();
+        for model in &models {
+            for material in &model.materials {
+                count_texture(&material.diffuse_texture);
+                count_texture(&material.normal_texture);
+                count_texture(&material.metallic_roughness_texture);
+            }
+        }
+        memory.meshes = models.iter().flat_map(|model| &model.meshes).map(|mesh| mesh.gpu_bytes()).sum();
+        memory
+    }
+}
+
+// `a/../b.png` and `b.png` are the same file
+fn key(file_name: &str) -> String {
+    vfs::normalize(file_name).unwrap_or_else(|| file_name.to_string())
+}
@@ -0,0 +1,83 @@
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use anyhow::anyhow;
+
+/// Environment variable with extra asset directories, separated like `PATH`.
+pub const ASSETS_ENV: &str = "WGPU_LEARN_ASSETS";
+
+/// Name of the asset directory next to the executable.
+pub const ASSETS_DIR: &str = "res";
+
+/// Directories assets are looked up in. The first one holding the file wins,
+/// so earlier directories override later ones file by file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchPaths {
+    dirs: Vec<PathBuf>,
+}
+
+impl SearchPaths {
+    pub fn new(dirs: impl IntoIterator<Item = PathBuf>) -> Self {
+        Self {
+            dirs: dirs.into_iter().collect(),
+        }
+    }
+
+    /// `dirs` first, usually from the command line, then the directories in
+    /// [`ASSETS_ENV`], then [`ASSETS_DIR`] next to the executable, and last
+    /// the copy of `res/` the build script leaves in the build directory, so
+    /// `cargo run` works without any setup.
+    pub fn with_defaults(dirs: impl IntoIterator<Item = PathBuf>) -> Self {
+        let mut paths = Self::new(dirs);
+        if let Some(env) = std::env::var_os(ASSETS_ENV) {
+            paths.dirs.extend(std::env::split_paths(&env).filter(|dir| !dir.as_os_str().is_empty()));
+        }
+        if let Some(exe_dir) = std::env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf)) {
+            paths.push(exe_dir.join(ASSETS_DIR));
+        }
+        paths.push(Path::new(env!("OUT_DIR")).join("res"));
+        paths
+    }
+
+    /// Adds a directory searched after the ones already there.
+    pub fn push(&mut self, dir: impl Into<PathBuf>) {
+        self.dirs.push(dir.into());
+    }
+
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    /// The path of `file_name` in the first directory that has it.
+    pub fn resolve(&self, file_name: &str) -> anyhow::Result<PathBuf> {
+        self.dirs
+            .iter()
+            .map(|dir| dir.join(file_name))
+            .find(|path| path.is_file())
+            .ok_or_else(|| anyhow!("cannot find asset {} in any of {:?}", file_name, self.dirs))
+    }
+}
+
+static SEARCH_PATHS: RwLock<Option<SearchPaths>> = RwLock::new(None);
+
+/// Makes every later asset load look in `paths`, unless a whole VFS was set
+/// with [`vfs::set_global`](crate::vfs::set_global). Call it before creating
+/// the renderer, as models and textures already loaded stay as they are.
+pub fn set_search_paths(paths: SearchPaths) {
+    *SEARCH_PATHS.write().unwrap_or_else(|e| e.into_inner()) = Some(paths);
+}
+
+/// The directories assets are loaded from: the ones last given to
+/// [`set_search_paths`], or [`SearchPaths::with_defaults`] without extra ones.
+pub fn search_paths() -> SearchPaths {
+    SEARCH_PATHS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap_or_else(|| SearchPaths::with_defaults([]))
+}
+
+/// See [`SearchPaths::resolve`].
+pub fn resolve(file_name: &str) -> anyhow::Result<PathBuf> {
+    search_paths().resolve(file_name)
+}
@@ -0,0 +1,41 @@
+//! Builds an asset pack out of a directory, for mounting with `--pack`.
+
+use wgpu_learn_01::pack::{Compression, PackBuilder};
+
+const USAGE: &str = "usage: pack [--store] OUTPUT DIR";
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{:?}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    let mut compression = Compression::Deflate;
+    let mut paths = Vec::new();
+    for arg in std::env::args_os().skip(1) {
+        if arg == "--store" {
+            compression = Compression::Store;
+        } else {
+            paths.push(std::path::PathBuf::from(arg));
+        }
+    }
+    let [output, dir] = <[_; 2]>::try_from(paths).map_err(|_| anyhow::anyhow!(USAGE))?;
+
+    let mut builder = PackBuilder::new(compression);
+    builder.add_dir(&dir)?;
+    let entries = builder.write(&output)?;
+
+    let size = entries.iter().map(|entry| entry.size).sum::<u64>();
+    let stored = entries.iter().map(|entry| entry.stored_size()).sum::<u64>();
+    println!(
+        "Packed {} files from {} into {}: {} bytes stored as {}",
+        entries.len(),
+        dir.display(),
+        output.display(),
+        size,
+        stored
+    );
+    Ok(())
+}
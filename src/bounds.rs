@@ -0,0 +1,51 @@
+/// Axis aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: cgmath::Point3<f32>,
+    pub max: cgmath::Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: cgmath::Point3<f32>, max: cgmath::Point3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    /// Smallest box around `points`, or `None` when there are none.
+    pub fn from_points(points: impl IntoIterator<Item = cgmath::Point3<f32>>) -> Option<Self> {
+        points.into_iter().fold(None, |bounds, point| {
+            Some(match bounds {
+                None => Self::new(point, point),
+                Some(bounds) => bounds.union(&Self::new(point, point)),
+            })
+        })
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: cgmath::Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: cgmath::Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    /// Box around this one after moving it by `matrix`. Rotations make it
+    /// grow, as the box has to stay axis aligned.
+    pub fn transform(&self, matrix: &cgmath::Matrix4<f32>) -> Self {
+        use cgmath::Transform;
+        let corners = (0..8).map(|i| {
+            matrix.transform_point(cgmath::Point3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            ))
+        });
+        Self::from_points(corners).unwrap_or(*self)
+    }
+}
@@ -1,26 +1,115 @@
-use winit::event::{WindowEvent, KeyboardInput, ElementState, VirtualKeyCode};
+use std::time::Duration;
+
+use cgmath::prelude::*;
+use winit::event::{WindowEvent, KeyboardInput, ElementState, VirtualKeyCode, MouseButton, MouseScrollDelta};
 
 use crate::Camera;
 
+/// Keeps the camera from flipping over when looking straight up or down.
+const MAX_PITCH: cgmath::Rad<f32> = cgmath::Rad(std::f32::consts::FRAC_PI_2 - 0.01);
+/// Closest the orbit camera gets to its target.
+const MIN_ORBIT_DISTANCE: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Circles `camera.target`. Keys orbit and dolly, dragging with the left
+    /// mouse button rotates and the scroll wheel zooms.
+    Orbit,
+    /// First person mouse look. WASD moves, E/Q move up and down and the
+    /// target is carried along in front of the eye.
+    FreeFly,
+}
+
 pub struct CameraController {
+    // Top speed in world units per second
     speed: f32,
+    // Units per second squared
+    acceleration: f32,
+    // Fraction of the velocity lost per second, as an exponential rate
+    damping: f32,
+    velocity: cgmath::Vector3<f32>,
+    sensitivity: f32,
+    mode: CameraMode,
     is_forward_pressed: bool,
     is_backward_pressed: bool,
     is_left_pressed: bool,
     is_right_pressed: bool,
+    is_up_pressed: bool,
+    is_down_pressed: bool,
+    is_dragging: bool,
+    // Mouse movement and scrolling since the last update
+    rotate_horizontal: f32,
+    rotate_vertical: f32,
+    scroll: f32,
 }
 
 impl CameraController {
+    /// `speed` is the top speed in world units per second.
     pub fn new(speed: f32) -> Self {
         Self {
             speed,
+            // Reach top speed in an eighth of a second
+            acceleration: speed * 8.0,
+            damping: 10.0,
+            velocity: cgmath::Vector3::zero(),
+            sensitivity: 0.004,
+            mode: CameraMode::Orbit,
             is_forward_pressed: false,
             is_backward_pressed: false,
             is_left_pressed: false,
             is_right_pressed: false,
+            is_up_pressed: false,
+            is_down_pressed: false,
+            is_dragging: false,
+            rotate_horizontal: 0.0,
+            rotate_vertical: 0.0,
+            scroll: 0.0,
         }
     }
 
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        self.mode = mode;
+        self.velocity = cgmath::Vector3::zero();
+        self.is_dragging = false;
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+    }
+
+    /// Top speed in world units per second.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    /// How quickly the camera gets up to speed, in units per second squared.
+    pub fn set_acceleration(&mut self, acceleration: f32) {
+        self.acceleration = acceleration;
+    }
+
+    /// How quickly the camera coasts to a stop once keys are released. The
+    /// velocity is multiplied by `exp(-damping * dt)` every update.
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping;
+    }
+
+    /// Current velocity in camera space: x right, y up, z forward.
+    pub fn velocity(&self) -> cgmath::Vector3<f32> {
+        self.velocity
+    }
+
+    /// Radians of rotation per unit of mouse movement.
+    pub fn set_sensitivity(&mut self, sensitivity: f32) {
+        self.sensitivity = sensitivity;
+    }
+
+    /// Wants the cursor hidden and grabbed so raw mouse motion can steer.
+    pub fn wants_cursor_grab(&self) -> bool {
+        self.mode == CameraMode::FreeFly
+    }
+
     pub fn process_events(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::KeyboardInput {
@@ -49,42 +138,173 @@ impl CameraController {
                         self.is_right_pressed = is_pressed;
                         true
                     }
+                    VirtualKeyCode::E => {
+                        self.is_up_pressed = is_pressed;
+                        true
+                    }
+                    VirtualKeyCode::Q => {
+                        self.is_down_pressed = is_pressed;
+                        true
+                    }
+                    VirtualKeyCode::Tab if is_pressed => {
+                        self.set_mode(match self.mode {
+                            CameraMode::Orbit => CameraMode::FreeFly,
+                            CameraMode::FreeFly => CameraMode::Orbit,
+                        });
+                        true
+                    }
                     _ => false,
                 }
             }
+            WindowEvent::MouseInput { button: MouseButton::Left, state, .. } => {
+                self.is_dragging = *state == ElementState::Pressed;
+                self.mode == CameraMode::Orbit
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    // Roughly one line per 20 pixels
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+                };
+                true
+            }
+            WindowEvent::Focused(false) => {
+                self.is_dragging = false;
+                false
+            }
             _ => false,
         }
     }
 
-    pub fn update_camera(&self, camera: &mut Camera) {
-        use cgmath::InnerSpace;
-        let forward = camera.target - camera.eye;
-        let forward_norm = forward.normalize();
-        let forward_mag = forward.magnitude();
-
-        // Prevents glitching when camera gets too close to the
-        // center of the scene.
-        if self.is_forward_pressed && forward_mag > self.speed {
-            camera.eye += forward_norm * self.speed;
+    /// Feeds raw mouse movement from `DeviceEvent::MouseMotion`. Only used
+    /// while dragging in orbit mode or at all times in free-fly mode.
+    pub fn process_mouse_motion(&mut self, dx: f64, dy: f64) {
+        if self.mode == CameraMode::FreeFly || self.is_dragging {
+            self.rotate_horizontal += dx as f32;
+            self.rotate_vertical += dy as f32;
         }
-        if self.is_backward_pressed {
-            camera.eye -= forward_norm * self.speed;
+    }
+
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+        let dt = dt.as_secs_f32();
+        self.update_velocity(dt);
+        match self.mode {
+            CameraMode::Orbit => self.update_orbit(camera, dt),
+            CameraMode::FreeFly => self.update_free_fly(camera, dt),
         }
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+        self.scroll = 0.0;
+    }
 
-        let right = forward_norm.cross(camera.up);
+    /// Speeds up towards the pressed keys and lets everything else die down.
+    fn update_velocity(&mut self, dt: f32) {
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let input = cgmath::Vector3::new(
+            axis(self.is_right_pressed, self.is_left_pressed),
+            axis(self.is_up_pressed, self.is_down_pressed),
+            axis(self.is_forward_pressed, self.is_backward_pressed),
+        );
+        let decay = (-self.damping * dt).exp();
 
-        // Redo radius calc in case the fowrard/backward is pressed.
-        let forward = camera.target - camera.eye;
-        let forward_mag = forward.magnitude();
+        if input.magnitude2() > 0.0 {
+            let input = input.normalize();
+            // Keep the speed we already have in the wanted direction, but damp
+            // sideways drift and movement the opposite way.
+            let speed_along = self.velocity.dot(input);
+            let along = input * speed_along;
+            let kept = if speed_along > 0.0 { along } else { along * decay };
+            self.velocity = kept + (self.velocity - along) * decay + input * self.acceleration * dt;
+            if self.velocity.magnitude() > self.speed {
+                self.velocity = self.velocity.normalize() * self.speed;
+            }
+        } else {
+            self.velocity *= decay;
+            if self.velocity.magnitude2() < 1e-6 {
+                self.velocity = cgmath::Vector3::zero();
+            }
+        }
+    }
 
-        if self.is_right_pressed {
-            // Rescale the distance between the target and eye so 
-            // that it doesn't change. The eye therefore still 
-            // lies on the circle made by the target and eye.
-            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
+    fn update_orbit(&mut self, camera: &mut Camera, dt: f32) {
+        if self.velocity == cgmath::Vector3::zero()
+            && self.rotate_horizontal == 0.0
+            && self.rotate_vertical == 0.0
+            && self.scroll == 0.0
+        {
+            return;
         }
-        if self.is_left_pressed {
-            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
+
+        // Everything works on the eye's spherical coordinates around the
+        // target. Forward velocity dollies in, sideways and vertical velocity
+        // move the eye around the target at that speed. Dragging moves the
+        // eye the opposite way, like grabbing the scene and turning it.
+        let offset = camera.eye - camera.target;
+        let distance = offset.magnitude();
+        let (yaw, pitch) = yaw_pitch(-offset, camera.up);
+        let yaw = yaw
+            - cgmath::Rad(self.velocity.x * dt / distance)
+            - cgmath::Rad(self.rotate_horizontal * self.sensitivity);
+        let pitch = clamp_pitch(
+            pitch
+                - cgmath::Rad(self.velocity.y * dt / distance)
+                - cgmath::Rad(self.rotate_vertical * self.sensitivity),
+        );
+
+        // Each scroll line moves 10% closer or further away
+        let mut distance = (distance - self.velocity.z * dt) * 0.9f32.powf(self.scroll);
+        // Prevents glitching when camera gets too close to the
+        // center of the scene.
+        if distance < MIN_ORBIT_DISTANCE {
+            distance = MIN_ORBIT_DISTANCE;
+            self.velocity.z = self.velocity.z.min(0.0);
         }
+        camera.eye = camera.target - direction(yaw, pitch, camera.up) * distance;
     }
+
+    fn update_free_fly(&self, camera: &mut Camera, dt: f32) {
+        let look = camera.target - camera.eye;
+        // Keep the target at the same distance so switching back to orbit
+        // mode circles something in front of the camera.
+        let look_distance = look.magnitude().max(MIN_ORBIT_DISTANCE);
+
+        let (yaw, pitch) = yaw_pitch(look, camera.up);
+        let yaw = yaw - cgmath::Rad(self.rotate_horizontal * self.sensitivity);
+        let pitch = clamp_pitch(pitch - cgmath::Rad(self.rotate_vertical * self.sensitivity));
+        let forward = direction(yaw, pitch, camera.up);
+        let right = forward.cross(camera.up).normalize();
+        let up = camera.up.normalize();
+
+        camera.eye += (right * self.velocity.x + up * self.velocity.y + forward * self.velocity.z) * dt;
+        camera.target = camera.eye + forward * look_distance;
+    }
+}
+
+/// A basis with `up` as its vertical axis, used to turn directions into
+/// yaw and pitch and back.
+fn basis(up: cgmath::Vector3<f32>) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
+    let up = up.normalize();
+    let reference = if up.z.abs() < 0.99 { cgmath::Vector3::unit_z() } else { cgmath::Vector3::unit_x() };
+    let side = up.cross(reference).normalize();
+    let front = side.cross(up);
+    (side, up, front)
+}
+
+fn yaw_pitch(direction: cgmath::Vector3<f32>, up: cgmath::Vector3<f32>) -> (cgmath::Rad<f32>, cgmath::Rad<f32>) {
+    let (side, up, front) = basis(up);
+    let direction = direction.normalize();
+    let yaw = cgmath::Rad(direction.dot(side).atan2(direction.dot(front)));
+    let pitch = cgmath::Rad(direction.dot(up).clamp(-1.0, 1.0).asin());
+    (yaw, pitch)
+}
+
+fn direction(yaw: cgmath::Rad<f32>, pitch: cgmath::Rad<f32>, up: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+    let (side, up, front) = basis(up);
+    let (sin_yaw, cos_yaw) = yaw.sin_cos();
+    let (sin_pitch, cos_pitch) = pitch.sin_cos();
+    (front * cos_yaw + side * sin_yaw) * cos_pitch + up * sin_pitch
+}
+
+fn clamp_pitch(pitch: cgmath::Rad<f32>) -> cgmath::Rad<f32> {
+    cgmath::Rad(pitch.0.clamp(-MAX_PITCH.0, MAX_PITCH.0))
 }
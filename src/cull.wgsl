@@ -0,0 +1,101 @@
+// Frustum culls instances on the GPU. Survivors are packed into their run's
+// range of `visible` and counted into indexed indirect draw arguments, one
+// set per mesh of the run's model.
+
+// Floats per InstanceRaw: model mat4, normal mat3 and tint color
+const INSTANCE_FLOATS: u32 = 29u;
+
+struct Cull {
+    // Inside where dot(xyz, p) + w >= 0
+    planes: array<vec4<f32>, 6>,
+    instance_count: u32,
+    run_count: u32,
+}
+@group(0) @binding(0)
+var<uniform> cull: Cull;
+@group(0) @binding(1)
+var<storage, read> instances: array<f32>;
+@group(0) @binding(2)
+var<storage, read_write> visible: array<f32>;
+
+// Laid out like wgpu's DrawIndexedIndirect
+struct DrawArgs {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+@group(0) @binding(3)
+var<storage, read_write> draws: array<DrawArgs>;
+
+// Consecutive instances sharing a model and material override
+struct Run {
+    bounds_min: vec4<f32>,
+    bounds_max: vec4<f32>,
+    first_instance: u32,
+    first_draw: u32,
+    // Zero when the run has nothing to draw
+    draw_count: u32,
+}
+@group(0) @binding(4)
+var<storage, read> runs: array<Run>;
+
+// The last run starting at or before `index`
+fn find_run(index: u32) -> u32 {
+    var low = 0u;
+    var high = cull.run_count;
+    while (high - low > 1u) {
+        let middle = (low + high) / 2u;
+        if (runs[middle].first_instance <= index) {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    return low;
+}
+
+fn column(start: u32) -> vec4<f32> {
+    return vec4<f32>(instances[start], instances[start + 1u], instances[start + 2u], instances[start + 3u]);
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= cull.instance_count) {
+        return;
+    }
+    let run = runs[find_run(index)];
+    if (run.draw_count == 0u) {
+        return;
+    }
+    let base = index * INSTANCE_FLOATS;
+    let model_matrix = mat4x4<f32>(column(base), column(base + 4u), column(base + 8u), column(base + 12u));
+
+    // Box around the transformed bounds, as a center and half extents
+    let center = (run.bounds_min.xyz + run.bounds_max.xyz) * 0.5;
+    let extent = (run.bounds_max.xyz - run.bounds_min.xyz) * 0.5;
+    let world_center = (model_matrix * vec4<f32>(center, 1.0)).xyz;
+    let world_extent = abs(model_matrix[0].xyz) * extent.x
+        + abs(model_matrix[1].xyz) * extent.y
+        + abs(model_matrix[2].xyz) * extent.z;
+
+    for (var i = 0; i < 6; i += 1) {
+        let plane = cull.planes[i];
+        if (dot(plane.xyz, world_center) + plane.w + dot(abs(plane.xyz), world_extent) < 0.0) {
+            return;
+        }
+    }
+
+    // Every mesh draws the same instances, so they all count the same
+    let slot = atomicAdd(&draws[run.first_draw].instance_count, 1u);
+    for (var mesh = 1u; mesh < run.draw_count; mesh += 1u) {
+        atomicAdd(&draws[run.first_draw + mesh].instance_count, 1u);
+    }
+
+    let out = (run.first_instance + slot) * INSTANCE_FLOATS;
+    for (var i = 0u; i < INSTANCE_FLOATS; i += 1u) {
+        visible[out + i] = instances[base + i];
+    }
+}
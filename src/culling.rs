@@ -0,0 +1,255 @@
+use std::ops::Range;
+
+use cgmath::prelude::*;
+
+use crate::bounds::Aabb;
+use crate::instances::{self, DirtyRange, DrawRun};
+use crate::{Instance, InstanceRaw};
+
+/// Plane with `normal` pointing into the inside, where
+/// `normal · p + distance >= 0`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: cgmath::Vector3<f32>,
+    pub distance: f32,
+}
+
+impl Plane {
+    fn from_row(row: cgmath::Vector4<f32>) -> Self {
+        let normal = row.truncate();
+        let length = normal.magnitude();
+        // A plane at infinity comes out with no normal. Left unnormalized it
+        // keeps everything inside.
+        if length > f32::EPSILON {
+            Self { normal: normal / length, distance: row.w / length }
+        } else {
+            Self { normal, distance: row.w }
+        }
+    }
+
+    /// Distance from the plane, negative on the outside.
+    pub fn signed_distance(&self, point: cgmath::Point3<f32>) -> f32 {
+        self.normal.dot(point.to_vec()) + self.distance
+    }
+}
+
+/// The six planes of a view frustum.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far.
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a view projection matrix producing wgpu clip
+    /// space, where depth runs from 0 to 1 in either direction.
+    pub fn from_matrix(matrix: &cgmath::Matrix4<f32>) -> Self {
+        let row = |i: usize| cgmath::Vector4::new(matrix.x[i], matrix.y[i], matrix.z[i], matrix.w[i]);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        Self {
+            planes: [
+                Plane::from_row(w + x),
+                Plane::from_row(w - x),
+                Plane::from_row(w + y),
+                Plane::from_row(w - y),
+                Plane::from_row(z),
+                Plane::from_row(w - z),
+            ],
+        }
+    }
+
+    pub fn contains_point(&self, point: cgmath::Point3<f32>) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(point) >= 0.0)
+    }
+
+    /// Conservative: boxes near a corner of the frustum can pass without
+    /// actually touching it, but a box that is visible never fails.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane's normal
+            let corner = cgmath::Point3::new(
+                if plane.normal.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.normal.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.normal.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            plane.signed_distance(corner) >= 0.0
+        })
+    }
+}
+
+/// Where instances outside the camera's view get dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Culling {
+    /// Draw every instance.
+    Off,
+    /// Test each instance on the CPU and upload only the visible ones.
+    Cpu,
+    /// Test instances in a compute shader and draw them indirectly, see
+    /// [`GpuCuller`](crate::gpu_culling::GpuCuller). Needs compute shaders
+    /// and indirect draws, which WebGL2 doesn't have.
+    ///
+    /// Instances are culled per run sharing a model and material override,
+    /// with one indirect draw per mesh of each run, so ordering the instance
+    /// list to keep such runs long keeps the draw count down.
+    Gpu,
+}
+
+/// How many instances the last cull looked at and how many survived.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullStats {
+    pub total: usize,
+    pub visible: usize,
+}
+
+impl CullStats {
+    pub fn culled(&self) -> usize {
+        self.total - self.visible
+    }
+}
+
+/// Indices of `instances` whose model's bounds, moved by the instance, reach
+/// into `frustum`. `bounds` holds one entry per model; instances of models
+/// without bounds have nothing to draw and never count as visible.
+pub fn visible_instances<'a>(
+    frustum: &'a Frustum,
+    instances: &'a [Instance],
+    bounds: &'a [Option<Aabb>],
+) -> impl Iterator<Item = usize> + 'a {
+    instances
+        .iter()
+        .enumerate()
+        .filter(move |(_, instance)| match bounds.get(instance.model) {
+            Some(Some(bounds)) => frustum.intersects_aabb(&bounds.transform(&instance.model_matrix())),
+            _ => false,
+        })
+        .map(|(index, _)| index)
+}
+
+/// Positions in `visible` whose instance data is out of date in the culled
+/// buffer, which last held the instances at `previous`. Both list indices
+/// into the full instance list in increasing order, and `changed` is the
+/// span of it edited since.
+///
+/// Everything from the first position where the lists part ways gets
+/// rewritten, plus the visible instances that fall into `changed`.
+pub fn stale_range(previous: &[usize], visible: &[usize], changed: Option<Range<usize>>) -> Option<Range<usize>> {
+    let kept = previous.iter().zip(visible).take_while(|(old, new)| old == new).count();
+    let mut stale = DirtyRange::default();
+    stale.mark(kept..visible.len());
+    if let Some(changed) = changed {
+        let kept = &visible[..kept];
+        let start = kept.partition_point(|&index| index < changed.start);
+        let end = kept.partition_point(|&index| index < changed.end);
+        stale.mark(start..end);
+    }
+    stale.take(visible.len())
+}
+
+/// The instances that passed culling, packed together in their own vertex
+/// buffer for the main pass. Only instances that came into view, moved
+/// within the buffer or were edited get written again.
+///
+/// The shadow and ID passes keep drawing from the full
+/// [`InstanceList`](crate::instances::InstanceList): objects off screen still
+/// cast shadows, and picking reports indices into the full list.
+pub struct CulledInstances {
+    visible: Vec<Instance>,
+    // Where `visible` sits in the full instance list
+    indices: Vec<usize>,
+    capacity: usize,
+    buffer: wgpu::Buffer,
+    stats: CullStats,
+}
+
+impl CulledInstances {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            visible: Vec::new(),
+            indices: Vec::new(),
+            capacity: 1,
+            buffer: Self::create_buffer(device, 1),
+            stats: CullStats::default(),
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Culled Instance Buffer"),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Culls `instances` against `frustum`, with `bounds` as for
+    /// [`visible_instances`], and uploads what [`stale_range`] finds out of
+    /// date. `changed` is the span of `instances` edited since the last
+    /// update, as reported by
+    /// [`InstanceList::dirty`](crate::instances::InstanceList::dirty) before
+    /// uploading the list. Without a `frustum` every instance counts as
+    /// visible.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frustum: Option<&Frustum>,
+        instances: &[Instance],
+        bounds: &[Option<Aabb>],
+        changed: Option<Range<usize>>,
+    ) {
+        let indices: Vec<usize> = match frustum {
+            Some(frustum) => visible_instances(frustum, instances, bounds).collect(),
+            None => (0..instances.len()).collect(),
+        };
+        let mut stale = stale_range(&self.indices, &indices, changed);
+        self.indices = indices;
+        self.visible.clear();
+        self.visible.extend(self.indices.iter().map(|&index| instances[index]));
+        self.stats = CullStats {
+            total: instances.len(),
+            visible: self.visible.len(),
+        };
+
+        let capacity = instances::grown_capacity(self.capacity, self.visible.len());
+        if capacity != self.capacity {
+            self.capacity = capacity;
+            self.buffer = Self::create_buffer(device, capacity);
+            stale = (!self.visible.is_empty()).then_some(0..self.visible.len());
+        }
+        if let Some(stale) = stale {
+            let raw = self.visible[stale.clone()].iter().map(Instance::to_raw).collect::<Vec<_>>();
+            queue.write_buffer(
+                &self.buffer,
+                (stale.start * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+                bytemuck::cast_slice(&raw),
+            );
+        }
+    }
+
+    /// Forgets what the buffer holds, so the next update writes every visible
+    /// instance. For when instances change without going through
+    /// [`CulledInstances::update`].
+    pub fn invalidate(&mut self) {
+        self.indices.clear();
+    }
+
+    pub fn stats(&self) -> CullStats {
+        self.stats
+    }
+    pub fn len(&self) -> usize {
+        self.visible.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.visible.is_empty()
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// See [`instances::draw_runs`].
+    pub fn draw_runs(&self) -> Vec<DrawRun> {
+        instances::draw_runs(&self.visible)
+    }
+}
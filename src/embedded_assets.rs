@@ -0,0 +1,19 @@
+use crate::vfs;
+
+// `static ASSETS`, generated by build.rs
+include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+
+/// The contents `res/` had at build time of the asset at `file_name`,
+/// relative to `res/` like for the search paths.
+pub fn get(file_name: &str) -> Option<&'static [u8]> {
+    let name = vfs::normalize(file_name)?;
+    ASSETS
+        .binary_search_by(|(asset, _)| (*asset).cmp(name.as_str()))
+        .ok()
+        .map(|index| ASSETS[index].1)
+}
+
+/// Every embedded asset, sorted.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    ASSETS.iter().map(|(name, _)| *name)
+}
@@ -0,0 +1,417 @@
+use std::ops::Range;
+
+use crate::asset_cache::Handle;
+use crate::bounds::Aabb;
+use crate::culling::{CullStats, Frustum};
+use crate::instances::{grown_capacity, InstanceList};
+use crate::model::Model;
+use crate::InstanceRaw;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniform {
+    planes: [[f32; 4]; 6],
+    instance_count: u32,
+    run_count: u32,
+    _padding: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct RunRaw {
+    bounds_min: [f32; 4],
+    bounds_max: [f32; 4],
+    first_instance: u32,
+    first_draw: u32,
+    draw_count: u32,
+    _padding: u32,
+}
+
+/// Arguments of one `draw_indexed_indirect` call.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DrawIndexedIndirectArgs {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub first_instance: u32,
+}
+
+impl DrawIndexedIndirectArgs {
+    pub const SIZE: wgpu::BufferAddress = std::mem::size_of::<Self>() as wgpu::BufferAddress;
+}
+
+/// Instances sharing a model and material override, culled together.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndirectRun {
+    pub model: usize,
+    pub material: Option<usize>,
+    /// The run's place in the instance list. The survivors are packed into
+    /// the start of the same range of [`GpuCuller::visible_buffer`].
+    pub instances: Range<u32>,
+    /// The run's [`DrawIndexedIndirectArgs`] in
+    /// [`GpuCuller::indirect_buffer`], one per mesh of the model. Empty when
+    /// there is nothing to draw, like for a model index past the end.
+    pub draws: Range<u32>,
+}
+
+/// Frustum culling in a compute shader, for instance counts where looping
+/// over them on the CPU every frame gets too slow.
+///
+/// Instances are culled in the runs [`InstanceList::draw_runs`] splits them
+/// into. Each run's visible instances land in its own range of the visible
+/// buffer in no particular order, and the indirect buffer holds one set of
+/// draw arguments per mesh of the run's model for
+/// [`DrawModel::draw_model_indirect`](crate::model::DrawModel::draw_model_indirect).
+/// The instance counts never come back to the CPU unless asked for with
+/// [`GpuCuller::read_stats`].
+pub struct GpuCuller {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: Option<wgpu::BindGroup>,
+    uniform_buffer: wgpu::Buffer,
+    run_buffer: wgpu::Buffer,
+    run_capacity: usize,
+    runs: Vec<IndirectRun>,
+    visible_buffer: wgpu::Buffer,
+    visible_capacity: usize,
+    indirect_buffer: wgpu::Buffer,
+    // The draw arguments with zero instances, copied over `indirect_buffer`
+    // before every dispatch so the counts start from scratch
+    initial_args_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    draw_capacity: usize,
+    draw_count: usize,
+    instance_count: u32,
+}
+
+impl GpuCuller {
+    /// Whether `device` has the compute shaders and storage buffers culling
+    /// needs. WebGL2 and some downlevel adapters don't.
+    pub fn is_supported(device: &wgpu::Device) -> bool {
+        let limits = device.limits();
+        limits.max_storage_buffers_per_shader_stage >= 4
+            && limits.max_compute_workgroup_size_x >= WORKGROUP_SIZE
+            && limits.max_compute_invocations_per_workgroup >= WORKGROUP_SIZE
+            && limits.max_compute_workgroups_per_dimension > 0
+    }
+
+    /// Builds the compute pipeline, which fails on devices that aren't
+    /// [`GpuCuller::is_supported`].
+    pub fn new(device: &wgpu::Device) -> Self {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("cull_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+                storage(3, false),
+                storage(4, true),
+            ],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Cull Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Cull Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("cull.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Cull Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull Uniform Buffer"),
+            size: std::mem::size_of::<CullUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            bind_group: None,
+            uniform_buffer,
+            run_buffer: Self::create_run_buffer(device, 1),
+            run_capacity: 1,
+            runs: Vec::new(),
+            visible_buffer: Self::create_visible_buffer(device, 1),
+            visible_capacity: 1,
+            indirect_buffer: Self::create_indirect_buffer(device, 1),
+            initial_args_buffer: Self::create_initial_args_buffer(device, 1),
+            readback_buffer: Self::create_readback_buffer(device, 1),
+            draw_capacity: 1,
+            draw_count: 0,
+            instance_count: 0,
+        }
+    }
+
+    fn create_run_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull Run Buffer"),
+            size: (capacity * std::mem::size_of::<RunRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_visible_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Visible Instance Buffer"),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_indirect_buffer(device: &wgpu::Device, draws: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Indirect Draw Buffer"),
+            size: draws as wgpu::BufferAddress * DrawIndexedIndirectArgs::SIZE,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_initial_args_buffer(device: &wgpu::Device, draws: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Initial Indirect Args Buffer"),
+            size: draws as wgpu::BufferAddress * DrawIndexedIndirectArgs::SIZE,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_readback_buffer(device: &wgpu::Device, draws: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull Readback Buffer"),
+            size: draws as wgpu::BufferAddress * DrawIndexedIndirectArgs::SIZE,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Prepares culling `instances`, which must already be uploaded, for
+    /// drawing `models` from inside `frustum`.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frustum: &Frustum,
+        instances: &InstanceList,
+        models: &[Handle<Model>],
+    ) {
+        // The compute pass counts instances up from zero, see `encode`
+        let mut runs = Vec::new();
+        let mut raw_runs = Vec::new();
+        let mut draws = Vec::new();
+        for run in instances.draw_runs() {
+            // A model without meshes draws nothing, whatever the bounds
+            let model = models.get(run.model);
+            let bounds = model.and_then(|model| model.bounds());
+            let first_draw = draws.len() as u32;
+            if bounds.is_some() {
+                draws.extend(model.into_iter().flat_map(|model| &model.meshes).map(|mesh| DrawIndexedIndirectArgs {
+                    index_count: mesh.num_elements,
+                    instance_count: 0,
+                    first_index: 0,
+                    base_vertex: 0,
+                    first_instance: 0,
+                }));
+            }
+            let bounds = bounds.unwrap_or(Aabb::new(cgmath::Point3::new(0.0, 0.0, 0.0), cgmath::Point3::new(0.0, 0.0, 0.0)));
+            raw_runs.push(RunRaw {
+                bounds_min: bounds.min.to_homogeneous().into(),
+                bounds_max: bounds.max.to_homogeneous().into(),
+                first_instance: run.instances.start,
+                first_draw,
+                draw_count: draws.len() as u32 - first_draw,
+                _padding: 0,
+            });
+            runs.push(IndirectRun {
+                model: run.model,
+                material: run.material,
+                instances: run.instances,
+                draws: first_draw..draws.len() as u32,
+            });
+        }
+
+        if instances.len() > self.visible_capacity {
+            self.visible_capacity = grown_capacity(self.visible_capacity, instances.len());
+            self.visible_buffer = Self::create_visible_buffer(device, self.visible_capacity);
+        }
+        if raw_runs.len() > self.run_capacity {
+            self.run_capacity = grown_capacity(self.run_capacity, raw_runs.len());
+            self.run_buffer = Self::create_run_buffer(device, self.run_capacity);
+        }
+        if draws.len() > self.draw_capacity {
+            self.draw_capacity = grown_capacity(self.draw_capacity, draws.len());
+            self.indirect_buffer = Self::create_indirect_buffer(device, self.draw_capacity);
+            self.initial_args_buffer = Self::create_initial_args_buffer(device, self.draw_capacity);
+            self.readback_buffer = Self::create_readback_buffer(device, self.draw_capacity);
+        }
+        self.instance_count = instances.len() as u32;
+        self.draw_count = draws.len();
+        self.runs = runs;
+
+        let uniform = CullUniform {
+            planes: frustum.planes.map(|plane| plane.normal.extend(plane.distance).into()),
+            instance_count: self.instance_count,
+            run_count: raw_runs.len() as u32,
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        if !raw_runs.is_empty() {
+            queue.write_buffer(&self.run_buffer, 0, bytemuck::cast_slice(&raw_runs));
+        }
+        if !draws.is_empty() {
+            queue.write_buffer(&self.initial_args_buffer, 0, bytemuck::cast_slice(&draws));
+        }
+
+        // The instance list may have swapped its buffer since the last frame
+        self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("cull_bind_group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: instances.buffer().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.visible_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.indirect_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.run_buffer.as_entire_binding(),
+                },
+            ],
+        }));
+    }
+
+    /// Records the culling dispatch. Has to run before the draws using
+    /// [`GpuCuller::visible_buffer`] and [`GpuCuller::indirect_buffer`].
+    /// Encoding again without an [`GpuCuller::update`] in between culls the
+    /// same instances again, rather than adding to the last counts.
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(bind_group) = &self.bind_group else {
+            return;
+        };
+        if self.draw_count > 0 {
+            encoder.copy_buffer_to_buffer(
+                &self.initial_args_buffer,
+                0,
+                &self.indirect_buffer,
+                0,
+                self.draw_count as wgpu::BufferAddress * DrawIndexedIndirectArgs::SIZE,
+            );
+        }
+        if self.instance_count == 0 {
+            return;
+        }
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Cull Pass"),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.instance_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    /// The runs the last [`GpuCuller::update`] split the instances into, in
+    /// instance list order.
+    pub fn runs(&self) -> &[IndirectRun] {
+        &self.runs
+    }
+
+    /// The visible instances, as a vertex buffer laid out like
+    /// [`InstanceRaw`].
+    pub fn visible_buffer(&self) -> &wgpu::Buffer {
+        &self.visible_buffer
+    }
+
+    /// [`DrawIndexedIndirectArgs`] for each mesh of each run, see
+    /// [`IndirectRun::draws`].
+    pub fn indirect_buffer(&self) -> &wgpu::Buffer {
+        &self.indirect_buffer
+    }
+
+    /// Reads the last cull's results back from the GPU, waiting for all
+    /// submitted work to finish.
+    pub fn read_stats(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<CullStats> {
+        let mut stats = CullStats {
+            total: self.instance_count as usize,
+            visible: 0,
+        };
+        if self.draw_count == 0 {
+            return Ok(stats);
+        }
+        let size = self.draw_count as wgpu::BufferAddress * DrawIndexedIndirectArgs::SIZE;
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Cull Readback Encoder"),
+        });
+        encoder.copy_buffer_to_buffer(&self.indirect_buffer, 0, &self.readback_buffer, 0, size);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = self.readback_buffer.slice(..size);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            tx.send(result).ok();
+        });
+        device.poll(wgpu::Maintain::Wait);
+        rx.recv()??;
+
+        {
+            let data = slice.get_mapped_range();
+            let draws: &[DrawIndexedIndirectArgs] = bytemuck::cast_slice(&data);
+            // Every mesh of a run draws the same instances, so the first one
+            // has the run's count
+            stats.visible = self
+                .runs
+                .iter()
+                .filter(|run| !run.draws.is_empty())
+                .map(|run| draws[run.draws.start as usize].instance_count as usize)
+                .sum();
+        }
+        self.readback_buffer.unmap();
+        Ok(stats)
+    }
+}
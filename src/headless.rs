@@ -0,0 +1,111 @@
+use anyhow::Context;
+
+use crate::renderer::Renderer;
+use crate::scene::{self, Scene};
+use crate::screenshot;
+
+/// Renders the scene into an offscreen texture instead of a window surface,
+/// so frames can be produced in CI or on machines without a display.
+pub struct HeadlessState {
+    renderer: Renderer,
+    target: wgpu::Texture,
+    target_view: wgpu::TextureView,
+}
+
+impl HeadlessState {
+    /// Offscreen color format. Matches the sRGB surfaces the shader expects and
+    /// reads back directly as RGBA8.
+    pub const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    /// Creates a headless renderer of the given size showing the default
+    /// scene.
+    ///
+    /// Tries the preferred adapter first and falls back to a software adapter if
+    /// no hardware one is available. `WGPU_BACKEND` can be used to restrict the
+    /// backends that are considered.
+    pub async fn new(width: u32, height: u32) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::all()),
+            dx12_shader_compiler: Default::default(),
+        });
+
+        let mut adapter = None;
+        for force_fallback_adapter in [false, true] {
+            adapter = instance.request_adapter(
+                &wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    compatible_surface: None,
+                    force_fallback_adapter,
+                },
+            ).await;
+            if adapter.is_some() {
+                break;
+            }
+        }
+        let adapter = adapter.context("no suitable graphics adapter found")?;
+        log::info!("headless adapter: {:?}", adapter.get_info());
+
+        let (device, queue) = adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                features: wgpu::Features::empty(),
+                // Software and GL adapters often can't meet the default limits,
+                // so ask for exactly what the adapter offers.
+                limits: adapter.limits(),
+                label: None,
+            },
+            None, //Trace path
+        ).await?;
+
+        let mut renderer = Renderer::new(device, queue, Self::COLOR_FORMAT, width, height).await?;
+        renderer.load_scene(&Scene::load_resource(scene::DEFAULT_SCENE).await?).await?;
+        let (target, target_view) = Self::create_target(&renderer.device, width, height);
+
+        Ok(Self {
+            renderer,
+            target,
+            target_view,
+        })
+    }
+
+    fn create_target(device: &wgpu::Device, width: u32, height: u32) -> (wgpu::Texture, wgpu::TextureView) {
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Headless Target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::COLOR_FORMAT,
+            // COPY_SRC so the frame can be read back after rendering
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        (target, target_view)
+    }
+
+    pub fn renderer(&self) -> &Renderer {
+        &self.renderer
+    }
+
+    pub fn renderer_mut(&mut self) -> &mut Renderer {
+        &mut self.renderer
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.renderer.resize(width, height);
+            (self.target, self.target_view) = Self::create_target(&self.renderer.device, width, height);
+        }
+    }
+
+    /// Renders one frame and returns its contents.
+    pub fn render(&mut self) -> anyhow::Result<image::RgbaImage> {
+        self.renderer.update();
+        self.renderer.render(&self.target_view);
+        screenshot::read_texture(&self.renderer.device, &self.renderer.queue, &self.target)
+    }
+}
@@ -0,0 +1,58 @@
+// Writes which instance and mesh covers each pixel, plus its depth, for
+// pixel exact picking. IDs are stored plus one so 0 means nothing.
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+}
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// The instance buffer is bound at `first_instance`, so `instance_index`
+// starts at zero for each draw.
+struct Draw {
+    mesh: u32,
+    first_instance: u32,
+}
+@group(1) @binding(0)
+var<uniform> draw: Draw;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) instance: u32,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.instance = draw.first_instance + instance_index;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<u32> {
+    // The depth's bits ride along in an integer channel
+    return vec4<u32>(in.instance + 1u, draw.mesh + 1u, bitcast<u32>(in.clip_position.z), 0u);
+}
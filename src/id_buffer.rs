@@ -0,0 +1,352 @@
+use crate::asset_cache::Handle;
+use crate::instances::DrawRun;
+use crate::model::{self, Vertex};
+use crate::{texture, InstanceRaw};
+
+const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Uint;
+// One texel of `ID_FORMAT`
+const PIXEL_SIZE: wgpu::BufferAddress = 16;
+
+// What one draw call of the ID pass writes. The instance buffer is bound at
+// the draw's first instance, so `instance_index` counts from zero on every
+// backend and `first_instance` is added back in the shader.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct DrawIds {
+    mesh: u32,
+    first_instance: u32,
+}
+
+/// What the ID buffer holds at a pixel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelHit {
+    /// Index into the instance list.
+    pub instance: usize,
+    /// Index into the meshes of the instance's model.
+    pub mesh: usize,
+    /// Depth buffer value of the surface, from 0 at the near plane to 1 at
+    /// the far plane, or the other way round with reverse-Z.
+    pub depth: f32,
+}
+
+/// Offscreen target the scene is drawn into with instance and mesh IDs in
+/// place of colors, so the exact surface under a pixel can be read back.
+pub struct IdBuffer {
+    width: u32,
+    height: u32,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    depth_texture: texture::Texture,
+    // One `DrawIds` per draw call, each at its own dynamic offset
+    draw_buffer: wgpu::Buffer,
+    draw_capacity: usize,
+    draw_stride: wgpu::BufferAddress,
+    draw_bind_group_layout: wgpu::BindGroupLayout,
+    draw_bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    reverse_pipeline: wgpu::RenderPipeline,
+    readback_buffer: wgpu::Buffer,
+}
+
+impl IdBuffer {
+    pub fn new(device: &wgpu::Device, camera_bind_group_layout: &wgpu::BindGroupLayout, width: u32, height: u32) -> Self {
+        let draw_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("id_draw_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<DrawIds>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let draw_stride = (std::mem::size_of::<DrawIds>() as wgpu::BufferAddress)
+            .max(device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress);
+        let draw_capacity = 1;
+        let (draw_buffer, draw_bind_group) =
+            Self::create_draw_buffer(device, &draw_bind_group_layout, draw_stride, draw_capacity);
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ID Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &draw_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("ID Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("id.wgsl").into()),
+        });
+        let pipeline = Self::create_pipeline(device, &layout, &shader, wgpu::CompareFunction::Less);
+        let reverse_pipeline = Self::create_pipeline(device, &layout, &shader, wgpu::CompareFunction::Greater);
+
+        let (texture, view) = Self::create_target(device, width, height);
+        let depth_texture = texture::Texture::create_depth_texture(device, width, height, false, "id_depth_texture");
+
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ID Readback Buffer"),
+            size: PIXEL_SIZE,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            width,
+            height,
+            texture,
+            view,
+            depth_texture,
+            draw_buffer,
+            draw_capacity,
+            draw_stride,
+            draw_bind_group_layout,
+            draw_bind_group,
+            pipeline,
+            reverse_pipeline,
+            readback_buffer,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        depth_compare: wgpu::CompareFunction,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("ID Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                // Integer targets can't blend
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: ID_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        })
+    }
+
+    fn create_target(device: &wgpu::Device, width: u32, height: u32) -> (wgpu::Texture, wgpu::TextureView) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("ID Target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: ID_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view)
+    }
+
+    fn create_draw_buffer(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        stride: wgpu::BufferAddress,
+        capacity: usize,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ID Draw Buffer"),
+            size: stride * capacity as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("id_draw_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<DrawIds>() as u64),
+                    }),
+                },
+            ],
+        });
+        (buffer, bind_group)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        if width > 0 && height > 0 && (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            (self.texture, self.view) = Self::create_target(device, width, height);
+            self.depth_texture = texture::Texture::create_depth_texture(device, width, height, false, "id_depth_texture");
+        }
+    }
+
+    /// Writes the IDs for every draw call [`IdBuffer::encode`] will make
+    /// for the same `models` and `runs`.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, models: &[Handle<model::Model>], runs: &[DrawRun]) {
+        let draws: Vec<DrawIds> = Self::draws(models, runs)
+            .map(|(run, mesh, _)| DrawIds {
+                mesh: mesh as u32,
+                first_instance: run.instances.start,
+            })
+            .collect();
+        if draws.len() > self.draw_capacity {
+            self.draw_capacity = draws.len().next_power_of_two();
+            (self.draw_buffer, self.draw_bind_group) =
+                Self::create_draw_buffer(device, &self.draw_bind_group_layout, self.draw_stride, self.draw_capacity);
+        }
+        for (i, draw) in draws.iter().enumerate() {
+            queue.write_buffer(
+                &self.draw_buffer,
+                i as wgpu::BufferAddress * self.draw_stride,
+                bytemuck::bytes_of(draw),
+            );
+        }
+    }
+
+    // Every mesh of every run's model, in draw order
+    fn draws<'a>(
+        models: &'a [Handle<model::Model>],
+        runs: &'a [DrawRun],
+    ) -> impl Iterator<Item = (&'a DrawRun, usize, &'a model::Mesh)> {
+        runs.iter()
+            .filter_map(|run| models.get(run.model).map(|model| (run, model)))
+            .flat_map(|(run, model)| model.meshes.iter().enumerate().map(move |(id, mesh)| (run, id, mesh)))
+    }
+
+    /// Records drawing the instance `runs` into the ID target. Call
+    /// [`IdBuffer::prepare`] with the same `models` and `runs` first.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        models: &[Handle<model::Model>],
+        camera_bind_group: &wgpu::BindGroup,
+        instance_buffer: &wgpu::Buffer,
+        runs: &[DrawRun],
+        reverse_z: bool,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("ID Pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &self.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(if reverse_z { 0.0 } else { 1.0 }),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        pass.set_pipeline(if reverse_z { &self.reverse_pipeline } else { &self.pipeline });
+        pass.set_bind_group(0, camera_bind_group, &[]);
+        let instance_size = std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress;
+        for (i, (run, _, mesh)) in Self::draws(models, runs).enumerate() {
+            let offset = i as wgpu::BufferAddress * self.draw_stride;
+            pass.set_bind_group(1, &self.draw_bind_group, &[offset as wgpu::DynamicOffset]);
+            pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            pass.set_vertex_buffer(1, instance_buffer.slice(run.instances.start as wgpu::BufferAddress * instance_size..));
+            pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            pass.draw_indexed(0..mesh.num_elements, 0, 0..run.instances.len() as u32);
+        }
+    }
+
+    /// Reads back the pixel at `x`, `y` from the last [`IdBuffer::encode`].
+    /// Blocks until the GPU is done.
+    pub fn read_pixel(&self, device: &wgpu::Device, queue: &wgpu::Queue, x: u32, y: u32) -> anyhow::Result<Option<PixelHit>> {
+        if x >= self.width || y >= self.height {
+            return Ok(None);
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("ID Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = self.readback_buffer.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            tx.send(result).ok();
+        });
+        device.poll(wgpu::Maintain::Wait);
+        rx.recv()??;
+
+        let texel: [u32; 4] = bytemuck::pod_read_unaligned(&slice.get_mapped_range());
+        self.readback_buffer.unmap();
+
+        let [instance, mesh, depth, _] = texel;
+        if instance == 0 {
+            return Ok(None);
+        }
+        Ok(Some(PixelHit {
+            instance: instance as usize - 1,
+            mesh: mesh as usize - 1,
+            depth: f32::from_bits(depth),
+        }))
+    }
+}
@@ -0,0 +1,215 @@
+use std::ops::Range;
+
+use crate::{Instance, InstanceRaw};
+
+/// The scene's instances and the vertex buffer they are drawn from.
+///
+/// Edits only touch the CPU copy and remember which part of it changed;
+/// [`InstanceList::upload`] then writes just that range, or reallocates the
+/// buffer when the instances no longer fit.
+pub struct InstanceList {
+    instances: Vec<Instance>,
+    // Indices changed since the last upload
+    dirty: DirtyRange,
+    capacity: usize,
+    buffer: wgpu::Buffer,
+}
+
+impl InstanceList {
+    pub fn new(device: &wgpu::Device, instances: Vec<Instance>) -> Self {
+        // Room for at least one so the buffer is never empty
+        let capacity = instances.len().max(1);
+        let buffer = Self::create_buffer(device, capacity);
+        let mut dirty = DirtyRange::default();
+        dirty.mark(0..instances.len());
+        Self {
+            instances,
+            dirty,
+            capacity,
+            buffer,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            // Storage for GPU culling to read from
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Appends an instance and returns its index.
+    pub fn push(&mut self, instance: Instance) -> usize {
+        self.instances.push(instance);
+        let index = self.instances.len() - 1;
+        self.dirty.mark(index..index + 1);
+        index
+    }
+
+    /// Removes the instance at `index`, moving the ones after it down.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> Instance {
+        let instance = self.instances.remove(index);
+        self.dirty.mark(index..self.instances.len());
+        instance
+    }
+
+    pub fn clear(&mut self) {
+        self.instances.clear();
+        self.dirty = DirtyRange::default();
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Instance> {
+        self.instances.get(index)
+    }
+
+    /// Marks the instance as changed, so only call this when actually editing.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Instance> {
+        if index < self.instances.len() {
+            self.dirty.mark(index..index + 1);
+        }
+        self.instances.get_mut(index)
+    }
+
+    /// Marks every instance as changed.
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Instance> {
+        self.dirty.mark(0..self.instances.len());
+        self.instances.iter_mut()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Instance> {
+        self.instances.iter()
+    }
+
+    pub fn as_slice(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Instances the buffer has room for.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Indices the next [`InstanceList::upload`] writes, unless it has to
+    /// reallocate.
+    pub fn dirty(&self) -> Option<Range<usize>> {
+        self.dirty.range()
+    }
+
+    /// See [`draw_runs`].
+    pub fn draw_runs(&self) -> Vec<DrawRun> {
+        draw_runs(&self.instances)
+    }
+
+    /// Writes changed instances to the GPU, growing the buffer when needed.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let capacity = grown_capacity(self.capacity, self.instances.len());
+        if capacity != self.capacity {
+            self.capacity = capacity;
+            self.buffer = Self::create_buffer(device, self.capacity);
+            // The new buffer starts out empty
+            self.dirty.mark(0..self.instances.len());
+        }
+
+        let Some(dirty) = self.dirty.take(self.instances.len()) else {
+            return;
+        };
+        let raw = self.instances[dirty.clone()].iter().map(Instance::to_raw).collect::<Vec<_>>();
+        queue.write_buffer(
+            &self.buffer,
+            (dirty.start * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            bytemuck::cast_slice(&raw),
+        );
+    }
+}
+
+/// The smallest span covering every index changed since it was last taken.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DirtyRange {
+    range: Option<Range<usize>>,
+}
+
+impl DirtyRange {
+    /// Adds `range`, growing the span to cover it. Empty ranges change
+    /// nothing.
+    pub fn mark(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        self.range = Some(match self.range.take() {
+            Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+            None => range,
+        });
+    }
+
+    pub fn range(&self) -> Option<Range<usize>> {
+        self.range.clone()
+    }
+
+    /// The span cut off at `len`, since removals can leave it reaching past
+    /// the end, or `None` if nothing below `len` changed. Resets the span
+    /// either way.
+    pub fn take(&mut self, len: usize) -> Option<Range<usize>> {
+        let range = self.range.take()?;
+        let range = range.start..range.end.min(len);
+        (!range.is_empty()).then_some(range)
+    }
+}
+
+/// Capacity for `len` items in a buffer holding `capacity`: the same while
+/// they fit, otherwise the next power of two so growing one at a time doesn't
+/// reallocate every time.
+pub fn grown_capacity(capacity: usize, len: usize) -> usize {
+    if len > capacity {
+        len.next_power_of_two()
+    } else {
+        capacity
+    }
+}
+
+/// Consecutive instances that one draw call can cover.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DrawRun {
+    pub model: usize,
+    /// Material override shared by the instances.
+    pub material: Option<usize>,
+    pub instances: Range<u32>,
+}
+
+/// Splits `instances` into runs sharing a model and material override, since
+/// a draw call can only bind one of each. Keeping such instances together
+/// keeps the number of draws down.
+pub fn draw_runs(instances: &[Instance]) -> Vec<DrawRun> {
+    let mut runs: Vec<DrawRun> = Vec::new();
+    for (index, instance) in instances.iter().enumerate() {
+        let index = index as u32;
+        match runs.last_mut() {
+            Some(run) if run.model == instance.model && run.material == instance.material => {
+                run.instances.end = index + 1;
+            }
+            _ => runs.push(DrawRun {
+                model: instance.model,
+                material: instance.material,
+                instances: index..index + 1,
+            }),
+        }
+    }
+    runs
+}
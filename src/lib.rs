@@ -1,20 +1,46 @@
 use camera_controller::CameraController;
-//use wgpu::RenderPipeline;
-use wgpu::util::DeviceExt;
 use winit::window::Window;
 use cgmath::prelude::*;
-
-
-
-mod resources;
-mod texture;
-mod model;
-mod camera_controller;
-
-use model::{Vertex, DrawModel};
+use serde::{Deserialize, Serialize};
+
+
+
+pub mod resources;
+pub mod asset_cache;
+pub mod asset_paths;
+#[cfg(feature = "embed-assets")]
+pub mod embedded_assets;
+pub mod pack;
+pub mod vfs;
+pub mod bounds;
+pub mod culling;
+pub mod gpu_culling;
+pub mod texture;
+pub mod model;
+pub mod camera_controller;
+pub mod projection;
+pub mod picking;
+pub mod id_buffer;
+pub mod renderer;
+pub mod light;
+pub mod instances;
+pub mod shadow;
+pub mod headless;
+pub mod screenshot;
+pub mod timing;
+pub mod scene;
+pub mod scene_graph;
+
+pub use renderer::Renderer;
+pub use headless::HeadlessState;
+pub use projection::Projection;
+pub use scene::Scene;
 
 // lib.rs
 
+/// Where F5 saves the scene, relative to the working directory.
+const SAVED_SCENE: &str = "scene.ron";
+
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
 	1.0, 0.0, 0.0, 0.0,
@@ -23,23 +49,66 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
 	0.0, 0.0, 0.5, 1.0,
 );
 
-struct Camera {
-	eye: cgmath::Point3<f32>,
-	target: cgmath::Point3<f32>,
-	up: cgmath::Vector3<f32>,
-	aspect: f32,
-	fovy: f32,
-	znear: f32,
-	zfar: f32,
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Camera {
+	pub eye: cgmath::Point3<f32>,
+	pub target: cgmath::Point3<f32>,
+	pub up: cgmath::Vector3<f32>,
+	/// Comes from the render target, so scene files leave it out.
+	#[serde(skip)]
+	pub aspect: f32,
+	pub projection: Projection,
+	/// Maps the near plane to depth 1 and the far plane to 0. Floats are far
+	/// denser near 0, so this spreads precision over the whole range instead
+	/// of wasting it close to the camera.
+	pub reverse_z: bool,
 }
 
 impl Camera {
 
-	fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+	pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
 		let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
-		let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+		let proj = self.projection.matrix(self.aspect, self.reverse_z);
 		
-		OPENGL_TO_WGPU_MATRIX * proj * view
+		proj * view
+	}
+
+	/// Ray from the camera through the pixel at `x`, `y`, counted from the top
+	/// left of a `width` by `height` image. The direction is normalized.
+	pub fn screen_ray(&self, x: f32, y: f32, width: f32, height: f32) -> picking::Ray {
+		let ndc_x = 2.0 * x / width - 1.0;
+		let ndc_y = 1.0 - 2.0 * y / height;
+		let inverse = self.build_view_projection_matrix().invert().unwrap_or_else(cgmath::Matrix4::identity);
+		// Any depth past the near plane lies on the ray. Halfway stays finite
+		// even when the far plane is at infinity.
+		let near_depth = if self.reverse_z { 1.0 } else { 0.0 };
+		let near = inverse.transform_point(cgmath::Point3::new(ndc_x, ndc_y, near_depth));
+		let further = inverse.transform_point(cgmath::Point3::new(ndc_x, ndc_y, 0.5));
+		picking::Ray::new(near, (further - near).normalize())
+	}
+
+	/// The planes bounding everything the camera can see.
+	pub fn frustum(&self) -> culling::Frustum {
+		culling::Frustum::from_matrix(&self.build_view_projection_matrix())
+	}
+
+	/// World space corners of the part of the view frustum between `near` and
+	/// `far`, near plane first.
+	pub fn frustum_corners(&self, near: f32, far: f32) -> [cgmath::Point3<f32>; 8] {
+		let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
+		let proj = self.projection.with_depth_range(near, far).matrix(self.aspect, false);
+		let inverse = (proj * view).invert().unwrap_or_else(cgmath::Matrix4::identity);
+
+		let mut corners = [cgmath::Point3::origin(); 8];
+		for (i, corner) in corners.iter_mut().enumerate() {
+			let ndc = cgmath::Point3::new(
+				if i & 1 == 0 { -1.0 } else { 1.0 },
+				if i & 2 == 0 { -1.0 } else { 1.0 },
+				if i & 4 == 0 { 0.0 } else { 1.0 },
+			);
+			*corner = inverse.transform_point(ndc);
+		}
+		corners
 	}
 }
 // We need this for Rust to store our data correctly for the shaders
@@ -47,6 +116,9 @@ impl Camera {
 // This is so we can store this in a buffer
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct CameraUniform {
+	// The eye position for specular lighting. Uniforms need 16 byte
+	// alignment, so this is a vec4 even though only xyz is used.
+	view_position: [f32; 4],
 	// We can't use cgmath with bytemuck directly so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
     view_proj: [[f32; 4]; 4],
@@ -55,35 +127,84 @@ struct CameraUniform {
 impl CameraUniform {
 	fn new() -> Self {
 		Self {
+			view_position: [0.0; 4],
 			view_proj: cgmath::Matrix4::identity().into(),
 		}
 	}
 
 	fn update_view_proj(&mut self, camera: &Camera) {
+		self.view_position = camera.eye.to_homogeneous().into();
 		self.view_proj = camera.build_view_projection_matrix().into();
 	}
 }
 
-struct Instance {
-	position: cgmath::Vector3<f32>,
-	rotation: cgmath::Quaternion<f32>,
+/// Fields missing from a scene file take their values from
+/// [`Instance::default`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Instance {
+	pub position: cgmath::Vector3<f32>,
+	pub rotation: cgmath::Quaternion<f32>,
+	/// Scale along the model's own axes, applied before the rotation.
+	pub scale: cgmath::Vector3<f32>,
+	/// Linear RGBA multiplied into the material's color.
+	pub color: [f32; 4],
+	/// Index into the model's materials used for every mesh instead of the
+	/// mesh's own material.
+	pub material: Option<usize>,
+	/// Index into the renderer's models.
+	pub model: usize,
+}
+
+impl Default for Instance {
+	/// The first model at the origin.
+	fn default() -> Self {
+		Self::new(cgmath::Vector3::zero(), cgmath::Quaternion::one())
+	}
 }
 
 impl Instance {
-	fn to_raw(&self) -> InstanceRaw {
+	/// An unscaled, untinted instance using the model's own materials.
+	pub fn new(position: cgmath::Vector3<f32>, rotation: cgmath::Quaternion<f32>) -> Self {
+		Self {
+			position,
+			rotation,
+			scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+			color: [1.0; 4],
+			material: None,
+			model: 0,
+		}
+	}
+
+	pub fn model_matrix(&self) -> cgmath::Matrix4<f32> {
+		cgmath::Matrix4::from_translation(self.position)
+			* cgmath::Matrix4::from(self.rotation)
+			* cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+	}
+
+	pub fn to_raw(&self) -> InstanceRaw {
+		let model = self.model_matrix();
+		// Normals need the inverse transpose of the model's linear part so they
+		// stay perpendicular to the surface under any transform.
+		let linear = cgmath::Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+		let normal = linear.invert().unwrap_or(linear).transpose();
 		InstanceRaw {
-			model: (cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation)).into(),
+			model: model.into(),
+			normal: normal.into(),
+			color: self.color,
 		}
 	}
 }
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct InstanceRaw {
+pub struct InstanceRaw {
 	model: [[f32; 4]; 4],
+	normal: [[f32; 3]; 3],
+	color: [f32; 4],
 }
 
 impl InstanceRaw {
-	fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+	pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
 		use std::mem;
 		wgpu::VertexBufferLayout {
 			array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,            // We need to switch from using a step mode of Vertex to Instance
@@ -116,6 +237,28 @@ impl InstanceRaw {
 					shader_location: 8,
 					format: wgpu::VertexFormat::Float32x4,
 				},
+				// The normal matrix, a mat3 spread over three vec3 slots
+				wgpu::VertexAttribute {
+					offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+					shader_location: 9,
+					format: wgpu::VertexFormat::Float32x3,
+				},
+				wgpu::VertexAttribute {
+					offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+					shader_location: 10,
+					format: wgpu::VertexFormat::Float32x3,
+				},
+				wgpu::VertexAttribute {
+					offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+					shader_location: 11,
+					format: wgpu::VertexFormat::Float32x3,
+				},
+				// Tint color
+				wgpu::VertexAttribute {
+					offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+					shader_location: 12,
+					format: wgpu::VertexFormat::Float32x4,
+				},
 			]
 		}
 	}
@@ -123,28 +266,21 @@ impl InstanceRaw {
 
 struct State {
     surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
     window: Window,
-    bg_color: wgpu::Color,
-	render_pipeline: wgpu::RenderPipeline,
-	camera: Camera,
-	camera_uniform: CameraUniform,
-	camera_buffer: wgpu::Buffer,
-	camera_bind_group: wgpu::BindGroup,
+	renderer: Renderer,
 	camera_controller: camera_controller::CameraController,
-	instances: Vec<Instance>,
-	instance_buffer: wgpu::Buffer,
-	depth_texture: texture::Texture,
-	obj_model: model::Model,
+	cursor_grabbed: bool,
+	cursor_position: winit::dpi::PhysicalPosition<f64>,
+	screenshot_requested: bool,
 }
 
 impl State {
 	
     // Creating some of the wgpu types requires async code
-    async fn new(window: Window) -> Self {
+    /// Shows the scene file at `scene_path`, or the default scene.
+    async fn new(window: Window, scene_path: Option<std::path::PathBuf>) -> Self {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
@@ -189,8 +325,7 @@ impl State {
         // sRGB surfaces, you'll need to account for that when drawing to the frame.
         let surface_format = surface_caps.formats.iter()
         	.copied()
-        	.filter(|f| f.describe().srgb)
-        	.next()
+        	.find(|f| f.describe().srgb)
         	.unwrap_or(surface_caps.formats[0]);
 
         let config = wgpu::SurfaceConfiguration {
@@ -204,213 +339,28 @@ impl State {
         };
         surface.configure(&device, &config);
 
-		let texture_bind_group_layout = device.create_bind_group_layout(
-			&wgpu::BindGroupLayoutDescriptor { 
-				label: Some("texture_binding_group_layout"),
-				entries: &[
-					wgpu::BindGroupLayoutEntry {
-						binding: 0,
-						visibility: wgpu::ShaderStages::FRAGMENT,
-						ty: wgpu::BindingType::Texture {
-							sample_type: wgpu::TextureSampleType::Float { filterable: true },
-							view_dimension: wgpu::TextureViewDimension::D2,
-							multisampled: false
-						},
-						count: None,
-					},
-					wgpu::BindGroupLayoutEntry {
-						binding: 1,
-						visibility: wgpu::ShaderStages::FRAGMENT,
-						ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-						count: None,
-					},
-				],
-			}
-		);
-
-		let camera = Camera {
-			// position the camera one unit up and 2 units back
-			// +z is out of the screen
-			eye: (0.0, 1.0, 2.0).into(),
-			// have it look at the origin
-			target: (0.0, 0.0, 0.0).into(),
-			// which way is "up"
-			up: cgmath::Vector3::unit_y(),
-			aspect: config.width as f32 / config.height as f32,
-			fovy: 45.0,
-			znear: 0.1,
-			zfar: 100.0,
+		let mut renderer = Renderer::new(device, queue, config.format, config.width, config.height)
+			.await
+			.unwrap();
+		let scene = match &scene_path {
+			Some(path) => Scene::load(path),
+			None => Scene::load_resource(scene::DEFAULT_SCENE).await,
 		};
+		renderer.load_scene(&scene.unwrap()).await.unwrap();
 
-		let mut camera_uniform = CameraUniform::new();
-		camera_uniform.update_view_proj(&camera);
-
-		let camera_buffer = device.create_buffer_init(
-			&wgpu::util::BufferInitDescriptor {
-				label: Some("Camera Buffer"),
-				contents: bytemuck::cast_slice(&[camera_uniform]),
-				usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-			}
-		);
-
-		let camera_bind_group_layout = device.create_bind_group_layout(
-			&wgpu::BindGroupLayoutDescriptor {
-				entries: &[
-					wgpu::BindGroupLayoutEntry {
-						binding: 0,
-						visibility: wgpu::ShaderStages::VERTEX,
-						ty: wgpu::BindingType::Buffer {
-							ty: wgpu::BufferBindingType::Uniform,
-							has_dynamic_offset: false,
-							min_binding_size: None,
-						},
-						count: None,
-					}
-				],
-				label: Some("camera_bind_group_layout"),
-			}
-		);
-
-		let camera_bind_group = device.create_bind_group(
-			&wgpu::BindGroupDescriptor {
-				layout: &camera_bind_group_layout,
-				entries: &[
-					wgpu::BindGroupEntry {
-						binding: 0,
-						resource: camera_buffer.as_entire_binding(),
-					}
-				],
-				label: Some("camera_bind_group"),
-			}
-		);
-
-		let camera_controller = CameraController::new(0.6);
-
-		const NUM_INSTANCES_PER_ROW: u32 = 10;
-		const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(NUM_INSTANCES_PER_ROW as f32 * 0.5, 0.0, NUM_INSTANCES_PER_ROW as f32 * 0.5);
-		const SPACE_BETWEEN: f32 = 3.0;
-
-		let instances = (0..NUM_INSTANCES_PER_ROW).flat_map(|z| {
-			(0..NUM_INSTANCES_PER_ROW).map(move |x| {
-				let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-				let z = SPACE_BETWEEN * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-				
-				let position = cgmath::Vector3 { x, y: 0.0, z};// - INSTANCE_DISPLACEMENT;
-				
-				let rotation = if position.is_zero() {
-					cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(),cgmath::Deg(0.0))
-				} else {
-					cgmath::Quaternion::from_axis_angle( position.normalize(),cgmath::Deg(45.0))
-				};
-
-				Instance {
-					position, rotation,
-				}
-			})
-		}).collect::<Vec<_>>();
-
-		let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-		let instance_buffer = device.create_buffer_init(
-			&wgpu::util::BufferInitDescriptor {
-				label: Some("Instance Buffer"),
-				contents: bytemuck::cast_slice(&instance_data),
-				usage: wgpu::BufferUsages::VERTEX,
-			}
-		);
-
-		let depth_texture = texture::Texture::create_depth_texture(&device, &config, "depth_texture");
-
-        let bg_color = wgpu::Color {
-			r: 0.005,
-			g: 0.005,
-			b: 0.005,
-			a: 1.0,
-		};
-        
-		let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-			label: Some("Shader"),
-			source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-		});
-
-		let render_pipeline_layout =
-			device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-				label: Some("Render Pipeline Layout"),
-				bind_group_layouts: &[
-					&texture_bind_group_layout,
-					&camera_bind_group_layout,
-				],
-				push_constant_ranges: &[],
-			});
-
-		let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-			label: Some("Render Pipeline"),
-			layout: Some(&render_pipeline_layout),
-			vertex: wgpu::VertexState {
-				module: &shader,
-				entry_point: "vs_main",					// vertex shader entrypoint
-				buffers: &[
-					model::ModelVertex::desc(),
-					InstanceRaw::desc(),
-				],							// vertex types?
-			},
-			fragment: Some(wgpu::FragmentState {		//optional?
-				module: &shader,
-				entry_point: "fs_main",					// fragment shader entrypoint
-				targets:								// what color outputs to use
-					&[Some(wgpu::ColorTargetState {
-						format: config.format,
-						blend: Some(wgpu::BlendState::REPLACE),		// just replace old pixel data
-					 	write_mask: wgpu::ColorWrites::ALL,			// write to all color-channels
-					})],
-			}),
-			primitive: wgpu::PrimitiveState {						// how to interpret vertices
-				topology: wgpu::PrimitiveTopology::TriangleList,	// every three vertices = 1 triangle
-				strip_index_format: None,
-				front_face: wgpu::FrontFace::Ccw,					// if arranged counterclockwise triangle is facing forwards
-				cull_mode: Some(wgpu::Face::Back),					// exclude triangles facing backwards
-				unclipped_depth: false,
-				polygon_mode: wgpu::PolygonMode::Fill,
-				conservative: false,
-			},
-			depth_stencil: Some(wgpu::DepthStencilState {
-				format: texture::Texture::DEPTH_FORMAT,
-				depth_write_enabled: true,
-				depth_compare: wgpu::CompareFunction::Less,
-				stencil: wgpu::StencilState::default(),
-				bias: wgpu::DepthBiasState::default(),
-			}),
-			multisample: wgpu::MultisampleState { 
-				count: 1,
-				mask: !0,
-				alpha_to_coverage_enabled: false,
-			},
-			multiview: None,
-		});
-
-		let obj_model =
-			resources::load_model("cube.obj", &device, &queue, &texture_bind_group_layout)
-				.await
-				.unwrap();
+		let camera_controller = CameraController::new(20.0);
 
         //return
         Self {
             window,
             surface,
-            device,
-            queue,
             config,
             size,
-            bg_color,
-			render_pipeline,
-			camera,
-			camera_uniform,
-			camera_buffer,
-			camera_bind_group,
+			renderer,
 			camera_controller,
-			instances,
-			instance_buffer,
-			depth_texture,
-			obj_model,
+			cursor_grabbed: false,
+			cursor_position: winit::dpi::PhysicalPosition::new(0.0, 0.0),
+			screenshot_requested: false,
         }
     }
 
@@ -423,13 +373,20 @@ impl State {
         	self.size = new_size;
         	self.config.width = new_size.width;
         	self.config.height = new_size.height;
-        	self.surface.configure(&self.device, &self.config);
-			self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+        	self.surface.configure(&self.renderer.device, &self.config);
+			self.renderer.resize(new_size.width, new_size.height);
         }
     }
 
     fn input(&mut self, event: &WindowEvent) -> bool {
 		self.camera_controller.process_events(event);
+		// Switching camera modes may change whether the cursor should be
+		// captured, and losing focus always releases it.
+		let focused = match event {
+			WindowEvent::Focused(focused) => *focused,
+			_ => true,
+		};
+		self.set_cursor_grab(focused && self.camera_controller.wants_cursor_grab());
 		match &event {
 			WindowEvent::KeyboardInput {
 				input: 
@@ -443,71 +400,353 @@ impl State {
 				println!("Spacebar pressed.");
 				true
 			}
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::F12),
+						..
+					},
+					..
+			} => {
+				self.screenshot_requested = true;
+				true
+			}
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::F5),
+						..
+					},
+					..
+			} => {
+				match self.renderer.scene().save(SAVED_SCENE) {
+					Ok(()) => println!("Saved the scene to {}", SAVED_SCENE),
+					Err(e) => eprintln!("Saving the scene failed: {:?}", e),
+				}
+				true
+			}
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::P),
+						..
+					},
+					..
+			} => {
+				self.cycle_projection();
+				true
+			}
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::Delete),
+						..
+					},
+					..
+			} => {
+				if let Some(selected) = self.renderer.selected_instance {
+					if let Err(e) = self.renderer.remove_instance(selected) {
+						eprintln!("Deleting the selection failed: {:?}", e);
+					}
+				}
+				true
+			}
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::Insert),
+						..
+					},
+					..
+			} => {
+				// Stack a copy on top of the selected instance and select it
+				if let Some(&instance) = self.renderer.selected_instance.and_then(|i| self.renderer.instances.get(i)) {
+					let index = self.renderer.instances.push(Instance {
+						position: instance.position + cgmath::Vector3::unit_y() * 2.5,
+						..instance
+					});
+					self.renderer.selected_instance = Some(index);
+				}
+				true
+			}
+			WindowEvent::CursorMoved { position, .. } => {
+				self.cursor_position = *position;
+				false
+			}
+			WindowEvent::MouseInput {
+				state: ElementState::Pressed,
+				button: MouseButton::Right,
+				..
+			} => {
+				let (x, y) = (self.cursor_position.x as f32, self.cursor_position.y as f32);
+				match self.renderer.select_at(x, y) {
+					Some(instance) => println!("Selected instance {}", instance),
+					None => println!("Nothing selected"),
+				}
+				true
+			}
+			WindowEvent::MouseInput {
+				state: ElementState::Pressed,
+				button: MouseButton::Middle,
+				..
+			} => {
+				let (x, y) = (self.cursor_position.x as u32, self.cursor_position.y as u32);
+				match self.renderer.pick_pixel(x, y) {
+					Ok(Some(hit)) => {
+						println!("Selected instance {} mesh {} at depth {}", hit.instance, hit.mesh, hit.depth);
+						self.renderer.selected_instance = Some(hit.instance);
+					}
+					Ok(None) => {
+						println!("Nothing selected");
+						self.renderer.selected_instance = None;
+					}
+					Err(e) => eprintln!("Picking failed: {:?}", e),
+				}
+				true
+			}
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::Z),
+						..
+					},
+					..
+			} => {
+				let camera = &mut self.renderer.camera;
+				camera.reverse_z = !camera.reverse_z;
+				println!("Reverse-Z: {}", camera.reverse_z);
+				true
+			}
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::C),
+						..
+					},
+					..
+			} => {
+				match self.renderer.cull_stats() {
+					Ok(stats) => println!(
+						"Last frame culled {} of {} instances",
+						stats.culled(),
+						stats.total,
+					),
+					Err(e) => eprintln!("Reading culling stats failed: {:?}", e),
+				}
+				self.renderer.culling = match self.renderer.culling {
+					Culling::Off => Culling::Cpu,
+					// WebGL2 and some downlevel adapters have no compute shaders
+					Culling::Cpu if !self.renderer.gpu_culling_supported() => Culling::Off,
+					Culling::Cpu => Culling::Gpu,
+					Culling::Gpu => Culling::Off,
+				};
+				println!("Frustum culling: {:?}", self.renderer.culling);
+				true
+			}
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::V),
+						..
+					},
+					..
+			} => {
+				let count = self.renderer.cameras().len();
+				let next = (self.renderer.active_camera() + 1) % count;
+				if let Err(e) = self.renderer.switch_camera(next) {
+					eprintln!("Switching cameras failed: {:?}", e);
+				}
+				println!("Camera {} of {}", next + 1, count);
+				true
+			}
 			_ => false
 		}
     }
 
-    fn update(&mut self) {
-        self.camera_controller.update_camera(&mut self.camera);
-		self.camera_uniform.update_view_proj(&self.camera);
-		self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+	/// Switches to the next projection type: perspective, orthographic,
+	/// infinite reverse-Z, off-center and back to perspective.
+	fn cycle_projection(&mut self) {
+		let camera = &mut self.renderer.camera;
+		let fovy = camera.projection.fovy().unwrap_or(cgmath::Deg(45.0));
+		let znear = camera.projection.znear();
+		let zfar = match camera.projection.zfar() {
+			zfar if zfar.is_finite() => zfar,
+			_ => 100.0,
+		};
+		camera.projection = match camera.projection {
+			Projection::Perspective { .. } => {
+				// Keep whatever is at the target about the same size
+				let distance = (camera.target - camera.eye).magnitude();
+				Projection::orthographic(2.0 * distance * (fovy / 2.0).tan(), znear, zfar)
+			}
+			Projection::Orthographic { .. } => {
+				// Without a far plane all the precision is needed
+				camera.reverse_z = true;
+				Projection::infinite_perspective(fovy, znear)
+			}
+			Projection::InfinitePerspective { .. } => {
+				Projection::off_center(fovy, cgmath::Vector2::new(0.5, 0.0), znear, zfar)
+			}
+			Projection::OffCenter { .. } => Projection::perspective(fovy, znear, zfar),
+		};
+		println!("Projection: {:?}", camera.projection);
+	}
+
+    fn device_input(&mut self, event: &DeviceEvent) {
+		// Raw motion keeps arriving when the cursor hits the screen edge,
+		// which is what mouse look needs.
+		if let DeviceEvent::MouseMotion { delta } = event {
+			self.camera_controller.process_mouse_motion(delta.0, delta.1);
+		}
+    }
+
+	/// Hides the cursor and locks it to the window, or releases it.
+	fn set_cursor_grab(&mut self, grab: bool) {
+		if grab == self.cursor_grabbed {
+			return;
+		}
+		let result = if grab {
+			// Not every platform can lock the cursor in place, confining it
+			// to the window is the next best thing.
+			self.window.set_cursor_grab(CursorGrabMode::Locked)
+				.or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Confined))
+		} else {
+			self.window.set_cursor_grab(CursorGrabMode::None)
+		};
+		match result {
+			Ok(()) => {
+				self.window.set_cursor_visible(!grab);
+				self.cursor_grabbed = grab;
+			}
+			Err(e) => eprintln!("Couldn't change cursor grab: {}", e),
+		}
+	}
+
+    /// Advances the simulation by `dt`. May run several times per frame with a
+    /// fixed timestep, so GPU uploads wait for `render`.
+    fn update(&mut self, dt: std::time::Duration) {
+        self.camera_controller.update_camera(&mut self.renderer.camera, dt);
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
         let output = self.surface.get_current_texture()?;
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
         
-        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-        	label: Some("Render Encoder"),
-        });
-        
-        {
-        	let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-        		label: Some("Render Pass"),
-        		color_attachments: &[
-					Some(wgpu::RenderPassColorAttachment {
-						view: &view,
-						resolve_target: None,
-						ops: wgpu::Operations {
-							load: wgpu::LoadOp::Clear(self.bg_color),
-							store: true,
-						},
-        			})
-				],
-        		depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-					view: &self.depth_texture.view,
-					depth_ops: Some(wgpu::Operations {
-						load: wgpu::LoadOp::Clear(1.0),
-						store: true,
-					}),
-					stencil_ops: None,
-				}),
-        	});
-
-			render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-			render_pass.set_pipeline(&self.render_pipeline);
-			render_pass.draw_model_instanced(&self.obj_model, 0..self.instances.len() as u32, &self.camera_bind_group);
-        }
-        
-        self.queue.submit(std::iter::once(encoder.finish()));
+		self.renderer.update();
+        self.renderer.render(&view);
         output.present();
+
+		if self.screenshot_requested {
+			self.screenshot_requested = false;
+			match self.save_screenshot(std::path::Path::new(Self::SCREENSHOT_DIR)) {
+				Ok(path) => println!("Saved screenshot to {}", path.display()),
+				Err(e) => eprintln!("Screenshot failed: {:?}", e),
+			}
+		}
         
         Ok(())
     }
+
+	const SCREENSHOT_DIR: &'static str = "screenshots";
+
+	/// Returns the current frame as an image.
+	pub fn screenshot(&self) -> anyhow::Result<image::RgbaImage> {
+		self.renderer.capture()
+	}
+
+	/// Saves the current frame as a timestamped PNG inside `dir`.
+	pub fn save_screenshot(&self, dir: &std::path::Path) -> anyhow::Result<std::path::PathBuf> {
+		screenshot::save_timestamped(&self.screenshot()?, dir)
+	}
 }
 
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{CursorGrabMode, WindowBuilder},
 };
+use timing::{FrameClock, Timestep};
+use culling::Culling;
+use asset_paths::SearchPaths;
+use vfs::Vfs;
+
+const USAGE: &str = "usage: wgpu-learn-01 [--assets DIR]... [--pack FILE]... [SCENE]";
+
+/// What [`run`] takes from the command line.
+#[derive(Debug, Default)]
+struct Args {
+	/// Asset directories searched before the packs and the default ones, in
+	/// order.
+	assets: Vec<std::path::PathBuf>,
+	/// Asset packs searched before the default directories, in order.
+	packs: Vec<std::path::PathBuf>,
+	/// Scene file to show instead of the default one.
+	scene: Option<std::path::PathBuf>,
+}
+
+impl Args {
+	fn parse(args: impl IntoIterator<Item = std::ffi::OsString>) -> anyhow::Result<Self> {
+		let mut parsed = Self::default();
+		let mut args = args.into_iter();
+		while let Some(arg) = args.next() {
+			let text = arg.to_str().unwrap_or_default();
+			if text == "--assets" {
+				let dir = args.next().ok_or_else(|| anyhow::anyhow!("--assets needs a directory"))?;
+				parsed.assets.push(dir.into());
+			} else if let Some(dir) = text.strip_prefix("--assets=") {
+				parsed.assets.push(dir.into());
+			} else if text == "--pack" {
+				let file = args.next().ok_or_else(|| anyhow::anyhow!("--pack needs a file"))?;
+				parsed.packs.push(file.into());
+			} else if let Some(file) = text.strip_prefix("--pack=") {
+				parsed.packs.push(file.into());
+			} else if text.starts_with("--") {
+				anyhow::bail!("unknown option {}", text);
+			} else if parsed.scene.is_none() {
+				parsed.scene = Some(arg.into());
+			} else {
+				anyhow::bail!("unexpected argument {:?}", arg);
+			}
+		}
+		Ok(parsed)
+	}
+}
 
 pub async fn run() {
     env_logger::init();
+	let args = match Args::parse(std::env::args_os().skip(1)) {
+		Ok(args) => args,
+		Err(e) => {
+			eprintln!("{}\n{}", e, USAGE);
+			std::process::exit(2);
+		}
+	};
+	// Loose files override packs so single assets can be patched
+	let mut vfs = Vfs::from_search_paths(&SearchPaths::with_defaults([]));
+	for dir in args.assets {
+		vfs.mount_dir(dir, 2);
+	}
+	for pack in &args.packs {
+		if let Err(e) = vfs.mount_pack(pack, 1) {
+			eprintln!("{:?}", e);
+			std::process::exit(1);
+		}
+	}
+	vfs::set_global(vfs);
+
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
 
-	let mut state = State::new(window).await;
+	let mut state = State::new(window, args.scene).await;
+	let mut clock = FrameClock::new(Timestep::Variable);
 
     event_loop.run(move |event, _, control_flow| {
 	    match event {
@@ -515,8 +754,7 @@ pub async fn run() {
 	        Event::WindowEvent {
 	            ref event,
 	            window_id,
-	        } if window_id == state.window().id() => {
-	        	if !state.input(event) {
+	        } if window_id == state.window().id() && !state.input(event) => {
 		        	match event {
 			            WindowEvent::CloseRequested 
 			            | WindowEvent::KeyboardInput {
@@ -537,10 +775,13 @@ pub async fn run() {
 			        	}
 			            _ => {}
 			        }
-			  	}
 			}
+			Event::DeviceEvent { ref event, .. } => state.device_input(event),
 			Event::RedrawRequested(window_id) if window_id == state.window().id() => {
-	    		state.update();
+	    		clock.tick();
+	    		for dt in clock.steps() {
+	    			state.update(dt);
+	    		}
 	    		match state.render() {
 	    			Ok(_) => {}
 	    			// Reconfigure the surface if lost
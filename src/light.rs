@@ -0,0 +1,300 @@
+use cgmath::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LightKind {
+    /// Shines in all directions from `position`.
+    Point,
+    /// Shines along `direction` from infinitely far away.
+    Directional,
+    /// Shines along `direction` from `position`, limited to a cone.
+    Spot,
+}
+
+/// Distance falloff: intensity is divided by
+/// `constant + linear * d + quadratic * d * d`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl Attenuation {
+    /// No falloff at all.
+    pub const NONE: Self = Self { constant: 1.0, linear: 0.0, quadratic: 0.0 };
+}
+
+/// Fields missing from a scene file take their values from
+/// [`Light::default`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Ignored for directional lights.
+    pub position: cgmath::Point3<f32>,
+    /// Ignored for point lights. Needn't be normalized; see
+    /// [`Light::unit_direction`] for what a zero vector means.
+    pub direction: cgmath::Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Distance at which the light fades out completely, or 0 for no limit.
+    /// Ignored for directional lights.
+    pub range: f32,
+    pub attenuation: Attenuation,
+    /// Full intensity inside this angle from `direction`. Spot lights only.
+    pub inner_cone: cgmath::Deg<f32>,
+    /// No light outside this angle from `direction`. Spot lights only.
+    pub outer_cone: cgmath::Deg<f32>,
+}
+
+impl Default for Light {
+    /// A white point light at the origin.
+    fn default() -> Self {
+        Self::point(cgmath::Point3::origin(), [1.0, 1.0, 1.0])
+    }
+}
+
+impl Light {
+    pub fn point(position: cgmath::Point3<f32>, color: [f32; 3]) -> Self {
+        Self {
+            kind: LightKind::Point,
+            position,
+            direction: -cgmath::Vector3::unit_y(),
+            color,
+            intensity: 1.0,
+            range: 0.0,
+            attenuation: Attenuation::NONE,
+            inner_cone: cgmath::Deg(0.0),
+            outer_cone: cgmath::Deg(0.0),
+        }
+    }
+
+    pub fn directional(direction: cgmath::Vector3<f32>, color: [f32; 3]) -> Self {
+        Self {
+            kind: LightKind::Directional,
+            direction,
+            ..Self::point(cgmath::Point3::origin(), color)
+        }
+    }
+
+    pub fn spot(
+        position: cgmath::Point3<f32>,
+        direction: cgmath::Vector3<f32>,
+        color: [f32; 3],
+        inner_cone: cgmath::Deg<f32>,
+        outer_cone: cgmath::Deg<f32>,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot,
+            direction,
+            inner_cone,
+            outer_cone,
+            ..Self::point(position, color)
+        }
+    }
+
+    /// `direction` normalized. A direction too short to normalize, like a
+    /// zero vector from a scene file, would turn into NaNs that spoil every
+    /// light and the shadow cascades, so it points straight down instead.
+    pub fn unit_direction(&self) -> cgmath::Vector3<f32> {
+        let length = self.direction.magnitude();
+        if length.is_finite() && length > f32::EPSILON {
+            self.direction / length
+        } else {
+            -cgmath::Vector3::unit_y()
+        }
+    }
+
+    fn to_raw(self) -> LightRaw {
+        LightRaw {
+            position: self.position.into(),
+            kind: match self.kind {
+                LightKind::Point => 0,
+                LightKind::Directional => 1,
+                LightKind::Spot => 2,
+            },
+            direction: self.unit_direction().into(),
+            range: self.range,
+            color: self.color,
+            intensity: self.intensity,
+            attenuation: [self.attenuation.constant, self.attenuation.linear, self.attenuation.quadratic],
+            cos_inner: self.inner_cone.cos(),
+            cos_outer: self.outer_cone.cos(),
+            _padding: [0; 3],
+        }
+    }
+}
+
+// Matches `Light` in shader.wgsl and light.wgsl. Storage buffer arrays use
+// 16 byte alignment, hence the vec3 + scalar pairs and the trailing padding.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightRaw {
+    position: [f32; 3],
+    kind: u32,
+    direction: [f32; 3],
+    range: f32,
+    color: [f32; 3],
+    intensity: f32,
+    attenuation: [f32; 3],
+    cos_inner: f32,
+    cos_outer: f32,
+    _padding: [u32; 3],
+}
+
+/// Light count, padded so the array that follows starts 16 byte aligned.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightHeader {
+    count: u32,
+    _padding: [u32; 3],
+}
+
+/// Handle returned by [`LightList::add`]. Stays valid until the light is
+/// removed, regardless of what happens to other lights.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LightId(u32);
+
+/// The scene's lights and the storage buffer they are uploaded to.
+///
+/// Changes are only recorded on the CPU; [`LightList::upload`] writes them to
+/// the GPU, growing the buffer when it runs out of room.
+pub struct LightList {
+    lights: Vec<(LightId, Light)>,
+    next_id: u32,
+    dirty: bool,
+    capacity: usize,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl LightList {
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout) -> Self {
+        // The shader's runtime sized array needs at least one element to bind.
+        let capacity = 4;
+        let (buffer, bind_group) = Self::create_buffer(device, layout, capacity);
+        Self {
+            lights: Vec::new(),
+            next_id: 0,
+            dirty: true,
+            capacity,
+            buffer,
+            bind_group,
+        }
+    }
+
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }
+            ],
+            label: Some("light_bind_group_layout"),
+        })
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        capacity: usize,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Buffer"),
+            size: (std::mem::size_of::<LightHeader>() + capacity * std::mem::size_of::<LightRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }
+            ],
+            label: Some("light_bind_group"),
+        });
+        (buffer, bind_group)
+    }
+
+    pub fn add(&mut self, light: Light) -> LightId {
+        let id = LightId(self.next_id);
+        self.next_id += 1;
+        self.lights.push((id, light));
+        self.dirty = true;
+        id
+    }
+
+    pub fn remove(&mut self, id: LightId) -> Option<Light> {
+        let index = self.lights.iter().position(|(i, _)| *i == id)?;
+        self.dirty = true;
+        Some(self.lights.remove(index).1)
+    }
+
+    pub fn get(&self, id: LightId) -> Option<&Light> {
+        self.lights.iter().find(|(i, _)| *i == id).map(|(_, light)| light)
+    }
+
+    /// Marks the list as changed, so only call this when actually editing.
+    pub fn get_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        let light = self.lights.iter_mut().find(|(i, _)| *i == id).map(|(_, light)| light)?;
+        self.dirty = true;
+        Some(light)
+    }
+
+    pub fn clear(&mut self) {
+        self.lights.clear();
+        self.dirty = true;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (LightId, &Light)> {
+        self.lights.iter().map(|(id, light)| (*id, light))
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Writes the lights to the GPU if anything changed since the last upload.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout) {
+        if !self.dirty {
+            return;
+        }
+        if self.lights.len() > self.capacity {
+            self.capacity = self.lights.len().next_power_of_two();
+            (self.buffer, self.bind_group) = Self::create_buffer(device, layout, self.capacity);
+        }
+
+        let header = LightHeader {
+            count: self.lights.len() as u32,
+            _padding: [0; 3],
+        };
+        let raw = self.lights.iter().map(|(_, light)| light.to_raw()).collect::<Vec<_>>();
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&header));
+        if !raw.is_empty() {
+            queue.write_buffer(
+                &self.buffer,
+                std::mem::size_of::<LightHeader>() as wgpu::BufferAddress,
+                bytemuck::cast_slice(&raw),
+            );
+        }
+        self.dirty = false;
+    }
+}
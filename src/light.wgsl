@@ -0,0 +1,64 @@
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Light {
+    position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    attenuation: vec3<f32>,
+    cos_inner: f32,
+    cos_outer: f32,
+}
+struct Lights {
+    count: u32,
+    lights: array<Light>,
+}
+@group(1) @binding(0)
+var<storage, read> lights: Lights;
+
+const LIGHT_DIRECTIONAL: u32 = 1u;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    @builtin(instance_index) light_index: u32,
+) -> VertexOutput {
+    let light = lights.lights[light_index];
+    var out: VertexOutput;
+    out.color = light.color;
+    if (light.kind == LIGHT_DIRECTIONAL) {
+        // Directional lights have no position; put the vertex outside the
+        // clip volume so nothing is drawn.
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        return out;
+    }
+    // Draw the model shrunk down at the light's position
+    let scale = 0.25;
+    out.clip_position = camera.view_proj * vec4<f32>(model.position * scale + light.position, 1.0);
+    return out;
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.color, 1.0);
+}
@@ -1,5 +1,10 @@
 use std::ops::Range;
 
+use wgpu::util::DeviceExt;
+
+use crate::asset_cache::Handle;
+use crate::bounds::Aabb;
+use crate::gpu_culling::DrawIndexedIndirectArgs;
 use crate::texture;
 
 pub trait Vertex { 
@@ -12,6 +17,8 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    pub tangent: [f32; 3],
+    pub bitangent: [f32; 3],
 }
 
 impl Vertex for ModelVertex {
@@ -35,6 +42,17 @@ impl Vertex for ModelVertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                // Tangent and bitangent
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 11]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         }
     }
@@ -42,21 +60,67 @@ impl Vertex for ModelVertex {
 
 
 pub trait DrawModel<'a> {
-    fn draw_mesh(&mut self, mesh: &'a Mesh, material: &'a Material, camera_bind_group: &'a wgpu::BindGroup);
+    fn draw_mesh(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'a Mesh,
         material: &'a Material,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
     );
 
-    fn draw_model(&mut self, model: &'a Model, camera_bind_group: &'a wgpu::BindGroup);
+    fn draw_model(
+        &mut self,
+        model: &'a Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
     fn draw_model_instanced(
         &mut self,
         model: &'a Model,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+    /// Draws each mesh with the [`DrawIndexedIndirectArgs`] at `offset` in
+    /// `indirect_buffer`.
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        indirect_buffer: &'a wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+    /// Draws the `i`th mesh with the `i`th set of [`DrawIndexedIndirectArgs`]
+    /// from `offset` on in `indirect_buffer`, as written by
+    /// [`GpuCuller`](crate::gpu_culling::GpuCuller). A `material` replaces
+    /// the meshes' own.
+    fn draw_model_indirect(
+        &mut self,
+        model: &'a Model,
+        material: Option<&'a Material>,
+        indirect_buffer: &'a wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+    /// Draws every mesh with `material` instead of its own.
+    fn draw_model_instanced_with_material(
+        &mut self,
+        model: &'a Model,
+        material: &'a Material,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
     );
 }
 
@@ -64,8 +128,14 @@ impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
 where
     'b: 'a, {
 
-        fn draw_mesh(&mut self, mesh: &'b Mesh, material: &'b Material, camera_bind_group: &'b wgpu::BindGroup) {
-            self.draw_mesh_instanced(mesh, material,0..1, camera_bind_group);
+        fn draw_mesh(
+            &mut self,
+            mesh: &'b Mesh,
+            material: &'b Material,
+            camera_bind_group: &'b wgpu::BindGroup,
+            light_bind_group: &'b wgpu::BindGroup,
+        ) {
+            self.draw_mesh_instanced(mesh, material, 0..1, camera_bind_group, light_bind_group);
         }
 
         fn draw_mesh_instanced(
@@ -74,16 +144,23 @@ where
             material: &'b Material,
             instances: Range<u32>,
             camera_bind_group: &'b wgpu::BindGroup,
+            light_bind_group: &'b wgpu::BindGroup,
         ) {
             self.set_vertex_buffer(0,mesh.vertex_buffer.slice(..));
             self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
             self.set_bind_group(0, &material.bind_group, &[]);
             self.set_bind_group(1, camera_bind_group, &[]);
+            self.set_bind_group(2, light_bind_group, &[]);
             self.draw_indexed(0..mesh.num_elements, 0, instances);
         }
 
-        fn draw_model(&mut self, model: &'b Model, camera_bind_group: &'b wgpu::BindGroup) {
-            self.draw_model_instanced(model, 0..1, camera_bind_group);
+        fn draw_model(
+            &mut self,
+            model: &'b Model,
+            camera_bind_group: &'b wgpu::BindGroup,
+            light_bind_group: &'b wgpu::BindGroup,
+        ) {
+            self.draw_model_instanced(model, 0..1, camera_bind_group, light_bind_group);
         }
 
         fn draw_model_instanced(
@@ -91,30 +168,304 @@ where
                 model: &'b Model,
                 instances: Range<u32>,
                 camera_bind_group: &'b wgpu::BindGroup,
+                light_bind_group: &'b wgpu::BindGroup,
             ) {
             for mesh in &model.meshes { 
                 let material = &model.materials[mesh.material];
-                self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group);
+                self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group, light_bind_group);
+            }
+        }
+
+        fn draw_mesh_indirect(
+            &mut self,
+            mesh: &'b Mesh,
+            material: &'b Material,
+            indirect_buffer: &'b wgpu::Buffer,
+            offset: wgpu::BufferAddress,
+            camera_bind_group: &'b wgpu::BindGroup,
+            light_bind_group: &'b wgpu::BindGroup,
+        ) {
+            self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            self.set_bind_group(0, &material.bind_group, &[]);
+            self.set_bind_group(1, camera_bind_group, &[]);
+            self.set_bind_group(2, light_bind_group, &[]);
+            self.draw_indexed_indirect(indirect_buffer, offset);
+        }
+
+        fn draw_model_indirect(
+            &mut self,
+            model: &'b Model,
+            material: Option<&'b Material>,
+            indirect_buffer: &'b wgpu::Buffer,
+            offset: wgpu::BufferAddress,
+            camera_bind_group: &'b wgpu::BindGroup,
+            light_bind_group: &'b wgpu::BindGroup,
+        ) {
+            for (index, mesh) in model.meshes.iter().enumerate() {
+                let material = material.unwrap_or(&model.materials[mesh.material]);
+                let offset = offset + index as wgpu::BufferAddress * DrawIndexedIndirectArgs::SIZE;
+                self.draw_mesh_indirect(mesh, material, indirect_buffer, offset, camera_bind_group, light_bind_group);
+            }
+        }
+
+        fn draw_model_instanced_with_material(
+            &mut self,
+            model: &'b Model,
+            material: &'b Material,
+            instances: Range<u32>,
+            camera_bind_group: &'b wgpu::BindGroup,
+            light_bind_group: &'b wgpu::BindGroup,
+        ) {
+            for mesh in &model.meshes {
+                self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group, light_bind_group);
+            }
+        }
+}
+
+pub trait DrawLight<'a> {
+    fn draw_light_mesh(
+        &mut self,
+        mesh: &'a Mesh,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+    fn draw_light_mesh_instanced(
+        &mut self,
+        mesh: &'a Mesh,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+
+    fn draw_light_model(
+        &mut self,
+        model: &'a Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+    fn draw_light_model_instanced(
+        &mut self,
+        model: &'a Model,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawLight<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a, {
+
+        fn draw_light_mesh(
+            &mut self,
+            mesh: &'b Mesh,
+            camera_bind_group: &'b wgpu::BindGroup,
+            light_bind_group: &'b wgpu::BindGroup,
+        ) {
+            self.draw_light_mesh_instanced(mesh, 0..1, camera_bind_group, light_bind_group);
+        }
+
+        fn draw_light_mesh_instanced(
+            &mut self,
+            mesh: &'b Mesh,
+            instances: Range<u32>,
+            camera_bind_group: &'b wgpu::BindGroup,
+            light_bind_group: &'b wgpu::BindGroup,
+        ) {
+            self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            self.set_bind_group(0, camera_bind_group, &[]);
+            self.set_bind_group(1, light_bind_group, &[]);
+            self.draw_indexed(0..mesh.num_elements, 0, instances);
+        }
+
+        fn draw_light_model(
+            &mut self,
+            model: &'b Model,
+            camera_bind_group: &'b wgpu::BindGroup,
+            light_bind_group: &'b wgpu::BindGroup,
+        ) {
+            self.draw_light_model_instanced(model, 0..1, camera_bind_group, light_bind_group);
+        }
+
+        fn draw_light_model_instanced(
+            &mut self,
+            model: &'b Model,
+            instances: Range<u32>,
+            camera_bind_group: &'b wgpu::BindGroup,
+            light_bind_group: &'b wgpu::BindGroup,
+        ) {
+            for mesh in &model.meshes {
+                self.draw_light_mesh_instanced(mesh, instances.clone(), camera_bind_group, light_bind_group);
+            }
+        }
+}
+
+
+/// Flat colored outline of a single instance, with only the camera bound.
+pub trait DrawHighlight<'a> {
+    fn draw_highlight_model(&mut self, model: &'a Model, instance: u32, camera_bind_group: &'a wgpu::BindGroup);
+}
+
+impl<'a, 'b> DrawHighlight<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a, {
+
+        fn draw_highlight_model(&mut self, model: &'b Model, instance: u32, camera_bind_group: &'b wgpu::BindGroup) {
+            self.set_bind_group(0, camera_bind_group, &[]);
+            for mesh in &model.meshes {
+                self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                self.draw_indexed(0..mesh.num_elements, 0, instance..instance + 1);
+            }
+        }
+}
+
+/// Depth-only drawing for shadow passes: geometry without materials.
+pub trait DrawShadow<'a> {
+    fn draw_shadow_mesh_instanced(&mut self, mesh: &'a Mesh, instances: Range<u32>);
+    fn draw_shadow_model_instanced(&mut self, model: &'a Model, instances: Range<u32>);
+}
+
+impl<'a, 'b> DrawShadow<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a, {
+
+        fn draw_shadow_mesh_instanced(&mut self, mesh: &'b Mesh, instances: Range<u32>) {
+            self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            self.draw_indexed(0..mesh.num_elements, 0, instances);
+        }
+
+        fn draw_shadow_model_instanced(&mut self, model: &'b Model, instances: Range<u32>) {
+            for mesh in &model.meshes {
+                self.draw_shadow_mesh_instanced(mesh, instances.clone());
             }
         }
 }
 
 
 pub struct Model { 
+    /// The file the model was loaded from.
+    pub name: String,
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
 }
 
+impl Model {
+    /// Bounds of all meshes together, or `None` for a model without meshes.
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.meshes.iter().map(|mesh| mesh.bounds).reduce(|a, b| a.union(&b))
+    }
+}
+
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: texture::Texture,
+    /// Shared with every other material using the same image file when
+    /// loaded through an [`AssetCache`](crate::asset_cache::AssetCache).
+    pub diffuse_texture: Handle<texture::Texture>,
+    pub normal_texture: Handle<texture::Texture>,
+    /// Metalness in the blue channel and roughness in the green one, as in
+    /// glTF. Holds linear data, and is plain white when the material has
+    /// none.
+    pub metallic_roughness_texture: Handle<texture::Texture>,
+    /// Linear RGBA the diffuse texture is multiplied with. Uploaded by
+    /// [`Material::new`], so changing it afterwards has no effect.
+    pub base_color_factor: [f32; 4],
+    /// Scales the metallic-roughness texture. Loaded from glTF files but not
+    /// used by the Blinn-Phong shading yet.
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
     pub bind_group: wgpu::BindGroup,
 }
 
+/// The constant factors of a [`Material`], defaulting to glTF's.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MaterialFactors {
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
+impl Default for MaterialFactors {
+    fn default() -> Self {
+        Self {
+            base_color: [1.0; 4],
+            metallic: 1.0,
+            roughness: 1.0,
+        }
+    }
+}
+
+impl Material {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        diffuse_texture: Handle<texture::Texture>,
+        normal_texture: Handle<texture::Texture>,
+        metallic_roughness_texture: Handle<texture::Texture>,
+        factors: MaterialFactors,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let factor_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Factor Buffer", name)),
+            contents: bytemuck::cast_slice(&factors.base_color),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: factor_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some(name),
+        });
+
+        Self {
+            name: name.to_string(),
+            diffuse_texture,
+            normal_texture,
+            metallic_roughness_texture,
+            base_color_factor: factors.base_color,
+            metallic_factor: factors.metallic,
+            roughness_factor: factors.roughness,
+            bind_group,
+        }
+    }
+}
+
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    /// Model space bounds of the vertices.
+    pub bounds: Aabb,
+}
+
+impl Mesh {
+    /// GPU memory of the vertex and index buffers.
+    pub fn gpu_bytes(&self) -> u64 {
+        self.vertex_buffer.size() + self.index_buffer.size()
+    }
 }
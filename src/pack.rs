@@ -0,0 +1,310 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context};
+
+use crate::vfs;
+
+// Layout, all numbers little endian:
+//
+//   header   MAGIC, VERSION: u32, index offset: u64
+//   data     the entries' bytes back to back, each maybe deflated
+//   index    entry count: u32, then per entry: name length: u16, name,
+//            offset: u64, stored size: u64, size: u64, compression: u8,
+//            CRC-32 of the uncompressed bytes: u32
+//
+// The index comes last so a pack can be written in one pass.
+const MAGIC: &[u8; 4] = b"WLPK";
+const VERSION: u32 = 1;
+const HEADER_SIZE: u64 = 16;
+
+/// How an entry's bytes are stored in the pack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Store,
+    Deflate,
+}
+
+impl Compression {
+    fn from_u8(value: u8) -> anyhow::Result<Self> {
+        match value {
+            0 => Ok(Self::Store),
+            1 => Ok(Self::Deflate),
+            _ => bail!("unknown compression {}", value),
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Store => 0,
+            Self::Deflate => 1,
+        }
+    }
+}
+
+/// Where an entry is in the pack file and how to get it back.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackEntry {
+    pub name: String,
+    offset: u64,
+    stored_size: u64,
+    /// Size once decompressed.
+    pub size: u64,
+    pub compression: Compression,
+    crc32: u32,
+}
+
+impl PackEntry {
+    /// Bytes the entry takes up in the pack.
+    pub fn stored_size(&self) -> u64 {
+        self.stored_size
+    }
+}
+
+/// An asset pack opened for reading. Only the index is kept in memory;
+/// entries are read from the file when asked for.
+pub struct Pack {
+    path: PathBuf,
+    // Sorted by name
+    entries: Vec<PackEntry>,
+}
+
+impl std::fmt::Debug for Pack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pack")
+            .field("path", &self.path)
+            .field("entries", &self.entries.len())
+            .finish()
+    }
+}
+
+impl Pack {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        Self::read_index(path).with_context(|| format!("cannot open pack {}", path.display()))
+    }
+
+    fn read_index(path: &Path) -> anyhow::Result<Self> {
+        let mut file = std::fs::File::open(path)?;
+        let mut header = [0; HEADER_SIZE as usize];
+        file.read_exact(&mut header)?;
+        ensure!(&header[0..4] == MAGIC, "not an asset pack");
+        let version = u32::from_le_bytes(header[4..8].try_into()?);
+        ensure!(version == VERSION, "unsupported pack version {}", version);
+        let index_offset = u64::from_le_bytes(header[8..16].try_into()?);
+
+        file.seek(SeekFrom::Start(index_offset))?;
+        let mut index = Vec::new();
+        file.read_to_end(&mut index)?;
+        let mut reader = index.as_slice();
+        let count = read_u32(&mut reader)?;
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let name_len = u16::from_le_bytes(take(&mut reader)?) as usize;
+            ensure!(reader.len() >= name_len, "truncated index");
+            let (name, rest) = reader.split_at(name_len);
+            reader = rest;
+            let entry = PackEntry {
+                name: String::from_utf8(name.to_vec()).context("entry name is not UTF-8")?,
+                offset: read_u64(&mut reader)?,
+                stored_size: read_u64(&mut reader)?,
+                size: read_u64(&mut reader)?,
+                compression: Compression::from_u8(take::<1>(&mut reader)?[0])?,
+                crc32: read_u32(&mut reader)?,
+            };
+            let end = entry.offset.checked_add(entry.stored_size);
+            ensure!(
+                entry.offset >= HEADER_SIZE && end.is_some_and(|end| end <= index_offset),
+                "entry {} lies outside the data",
+                entry.name
+            );
+            entries.push(entry);
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        ensure!(
+            entries.windows(2).all(|pair| pair[0].name != pair[1].name),
+            "duplicate entry names"
+        );
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every entry, sorted by name.
+    pub fn entries(&self) -> &[PackEntry] {
+        &self.entries
+    }
+
+    pub fn entry(&self, file_name: &str) -> Option<&PackEntry> {
+        let name = vfs::normalize(file_name)?;
+        self.entries
+            .binary_search_by(|entry| entry.name.as_str().cmp(&name))
+            .ok()
+            .map(|index| &self.entries[index])
+    }
+
+    /// The uncompressed bytes of `file_name`, or `None` if the pack doesn't
+    /// have it. Fails if they don't match the checksum they were packed with.
+    pub fn read(&self, file_name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(entry) = self.entry(file_name) else {
+            return Ok(None);
+        };
+        self.read_entry(entry)
+            .with_context(|| format!("cannot read {} from pack {}", entry.name, self.path.display()))
+            .map(Some)
+    }
+
+    fn read_entry(&self, entry: &PackEntry) -> anyhow::Result<Vec<u8>> {
+        let mut file = std::fs::File::open(&self.path)?;
+        file.seek(SeekFrom::Start(entry.offset))?;
+        let mut stored = vec![0; entry.stored_size as usize];
+        file.read_exact(&mut stored)?;
+        let data = match entry.compression {
+            Compression::Store => stored,
+            Compression::Deflate => {
+                let mut data = Vec::with_capacity(entry.size as usize);
+                flate2::read::DeflateDecoder::new(stored.as_slice()).read_to_end(&mut data)?;
+                data
+            }
+        };
+        ensure!(data.len() as u64 == entry.size, "size mismatch");
+        ensure!(crc32fast::hash(&data) == entry.crc32, "checksum mismatch");
+        Ok(data)
+    }
+}
+
+fn take<const N: usize>(reader: &mut &[u8]) -> anyhow::Result<[u8; N]> {
+    ensure!(reader.len() >= N, "truncated index");
+    let (bytes, rest) = reader.split_at(N);
+    *reader = rest;
+    Ok(bytes.try_into()?)
+}
+
+fn read_u32(reader: &mut &[u8]) -> anyhow::Result<u32> {
+    Ok(u32::from_le_bytes(take(reader)?))
+}
+
+fn read_u64(reader: &mut &[u8]) -> anyhow::Result<u64> {
+    Ok(u64::from_le_bytes(take(reader)?))
+}
+
+/// Collects files and writes them out as a pack.
+///
+/// With [`Compression::Deflate`], entries that don't get smaller (already
+/// compressed images, say) are stored as they are.
+pub struct PackBuilder {
+    compression: Compression,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl PackBuilder {
+    pub fn new(compression: Compression) -> Self {
+        Self {
+            compression,
+            files: Vec::new(),
+        }
+    }
+
+    /// Adds a file under `name`, a path relative to the pack's root with `/`
+    /// between directories. Replaces any earlier file of the same name.
+    pub fn add(&mut self, name: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        let name = vfs::normalize(name)
+            .filter(|name| !name.is_empty())
+            .with_context(|| format!("invalid entry name {:?}", name))?;
+        ensure!(name.len() <= u16::MAX as usize, "entry name {} is too long", name);
+        self.files.retain(|(existing, _)| *existing != name);
+        self.files.push((name, data));
+        Ok(())
+    }
+
+    /// Adds every file below `dir`, named by its path relative to `dir`.
+    pub fn add_dir(&mut self, dir: impl AsRef<Path>) -> anyhow::Result<()> {
+        let dir = dir.as_ref();
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(current) = pending.pop() {
+            let read_dir = std::fs::read_dir(&current).with_context(|| format!("cannot read {}", current.display()))?;
+            for entry in read_dir {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                let name = path
+                    .strip_prefix(dir)?
+                    .to_str()
+                    .with_context(|| format!("{} is not a UTF-8 path", path.display()))?
+                    .to_string();
+                let data = std::fs::read(&path).with_context(|| format!("cannot read {}", path.display()))?;
+                self.add(&name, data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the pack, with the entries sorted by name so the same files
+    /// always give the same pack.
+    pub fn write_to(mut self, mut writer: impl Write) -> anyhow::Result<Vec<PackEntry>> {
+        self.files.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut entries = Vec::with_capacity(self.files.len());
+        let mut offset = HEADER_SIZE;
+        let mut data_section = Vec::new();
+        for (name, data) in self.files {
+            let size = data.len() as u64;
+            let crc32 = crc32fast::hash(&data);
+            let (compression, stored) = match self.compression {
+                Compression::Store => (Compression::Store, data),
+                Compression::Deflate => {
+                    let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+                    encoder.write_all(&data)?;
+                    let deflated = encoder.finish()?;
+                    if deflated.len() < data.len() {
+                        (Compression::Deflate, deflated)
+                    } else {
+                        (Compression::Store, data)
+                    }
+                }
+            };
+            entries.push(PackEntry {
+                name,
+                offset,
+                stored_size: stored.len() as u64,
+                size,
+                compression,
+                crc32,
+            });
+            offset += stored.len() as u64;
+            data_section.extend_from_slice(&stored);
+        }
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&offset.to_le_bytes())?;
+        writer.write_all(&data_section)?;
+        writer.write_all(&(entries.len() as u32).to_le_bytes())?;
+        for entry in &entries {
+            writer.write_all(&(entry.name.len() as u16).to_le_bytes())?;
+            writer.write_all(entry.name.as_bytes())?;
+            writer.write_all(&entry.offset.to_le_bytes())?;
+            writer.write_all(&entry.stored_size.to_le_bytes())?;
+            writer.write_all(&entry.size.to_le_bytes())?;
+            writer.write_all(&[entry.compression.to_u8()])?;
+            writer.write_all(&entry.crc32.to_le_bytes())?;
+        }
+        writer.flush()?;
+        Ok(entries)
+    }
+
+    /// See [`PackBuilder::write_to`].
+    pub fn write(self, path: impl AsRef<Path>) -> anyhow::Result<Vec<PackEntry>> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path).with_context(|| format!("cannot create {}", path.display()))?;
+        self.write_to(std::io::BufWriter::new(file))
+            .with_context(|| format!("cannot write pack {}", path.display()))
+    }
+}
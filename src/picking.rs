@@ -0,0 +1,84 @@
+use cgmath::prelude::*;
+
+use crate::bounds::Aabb;
+use crate::Instance;
+
+/// Half line starting at `origin`. `direction` doesn't have to be normalized;
+/// distances along the ray are measured in multiples of it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: cgmath::Point3<f32>,
+    pub direction: cgmath::Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: cgmath::Point3<f32>, direction: cgmath::Vector3<f32>) -> Self {
+        Self { origin, direction }
+    }
+
+    pub fn at(&self, distance: f32) -> cgmath::Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// The same ray in the space `matrix` maps into. Distances stay the same
+    /// since the direction is transformed along with the origin.
+    pub fn transform(&self, matrix: &cgmath::Matrix4<f32>) -> Self {
+        Self {
+            origin: matrix.transform_point(self.origin),
+            direction: matrix.transform_vector(self.direction),
+        }
+    }
+
+    /// Distance to where the ray enters `aabb`, 0 if it starts inside, or
+    /// `None` if it misses.
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        // Slab test: clip the ray against the pair of planes on each axis.
+        let mut enter = 0.0f32;
+        let mut exit = f32::INFINITY;
+        for axis in 0..3 {
+            let origin = self.origin[axis];
+            let direction = self.direction[axis];
+            let (min, max) = (aabb.min[axis], aabb.max[axis]);
+            if direction == 0.0 {
+                if origin < min || origin > max {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (min - origin) / direction;
+            let t1 = (max - origin) / direction;
+            enter = enter.max(t0.min(t1));
+            exit = exit.min(t0.max(t1));
+            if enter > exit {
+                return None;
+            }
+        }
+        Some(enter)
+    }
+}
+
+/// The closest instance a ray hits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InstanceHit {
+    /// Index into the instance list.
+    pub instance: usize,
+    /// Distance along the ray in world units, provided the ray direction is
+    /// normalized.
+    pub distance: f32,
+}
+
+/// Casts `ray` against the bounds of every instance's model, given in model
+/// space, so the boxes turn with their instances instead of growing to stay
+/// axis aligned. `bounds` holds one entry per model.
+pub fn pick_instance(ray: &Ray, instances: &[Instance], bounds: &[Option<Aabb>]) -> Option<InstanceHit> {
+    instances
+        .iter()
+        .enumerate()
+        .filter_map(|(index, instance)| {
+            let bounds = bounds.get(instance.model)?.as_ref()?;
+            let to_local = instance.model_matrix().invert()?;
+            let distance = ray.transform(&to_local).intersect_aabb(bounds)?;
+            Some(InstanceHit { instance: index, distance })
+        })
+        .min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
@@ -0,0 +1,157 @@
+use cgmath::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::OPENGL_TO_WGPU_MATRIX;
+
+/// Flips wgpu depth from 0..1 to 1..0, so the near plane ends up at 1.
+#[rustfmt::skip]
+const REVERSE_Z_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0, 1.0, 1.0,
+);
+
+/// How the camera maps view space onto the screen. Every variant takes the
+/// horizontal extent from the camera's aspect ratio, so resizing the window
+/// never stretches the image.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    Perspective {
+        fovy: cgmath::Deg<f32>,
+        znear: f32,
+        zfar: f32,
+    },
+    /// Parallel projection showing `height` world units from bottom to top.
+    Orthographic {
+        height: f32,
+        znear: f32,
+        zfar: f32,
+    },
+    /// Perspective without a far plane. Meant for reverse-Z, where depth is 1
+    /// at `znear` and falls towards 0 at infinity, which keeps float depth
+    /// precise far away.
+    InfinitePerspective {
+        fovy: cgmath::Deg<f32>,
+        znear: f32,
+    },
+    /// Perspective whose center is moved off the view axis, like a shifted
+    /// camera lens. `shift` is in half-extents of the image, so `(1, 0)` puts
+    /// the view axis on the left edge.
+    OffCenter {
+        fovy: cgmath::Deg<f32>,
+        shift: cgmath::Vector2<f32>,
+        znear: f32,
+        zfar: f32,
+    },
+}
+
+impl Projection {
+    pub fn perspective(fovy: cgmath::Deg<f32>, znear: f32, zfar: f32) -> Self {
+        Self::Perspective { fovy, znear, zfar }
+    }
+
+    pub fn orthographic(height: f32, znear: f32, zfar: f32) -> Self {
+        Self::Orthographic { height, znear, zfar }
+    }
+
+    pub fn infinite_perspective(fovy: cgmath::Deg<f32>, znear: f32) -> Self {
+        Self::InfinitePerspective { fovy, znear }
+    }
+
+    pub fn off_center(fovy: cgmath::Deg<f32>, shift: cgmath::Vector2<f32>, znear: f32, zfar: f32) -> Self {
+        Self::OffCenter { fovy, shift, znear, zfar }
+    }
+
+    pub fn znear(&self) -> f32 {
+        match *self {
+            Self::Perspective { znear, .. }
+            | Self::Orthographic { znear, .. }
+            | Self::InfinitePerspective { znear, .. }
+            | Self::OffCenter { znear, .. } => znear,
+        }
+    }
+
+    /// Infinity for [`Projection::InfinitePerspective`].
+    pub fn zfar(&self) -> f32 {
+        match *self {
+            Self::Perspective { zfar, .. }
+            | Self::Orthographic { zfar, .. }
+            | Self::OffCenter { zfar, .. } => zfar,
+            Self::InfinitePerspective { .. } => f32::INFINITY,
+        }
+    }
+
+    /// Vertical field of view, or `None` for orthographic projections.
+    pub fn fovy(&self) -> Option<cgmath::Deg<f32>> {
+        match *self {
+            Self::Perspective { fovy, .. }
+            | Self::InfinitePerspective { fovy, .. }
+            | Self::OffCenter { fovy, .. } => Some(fovy),
+            Self::Orthographic { .. } => None,
+        }
+    }
+
+    /// The same projection limited to depths between `znear` and `zfar`,
+    /// always with a finite far plane.
+    pub fn with_depth_range(&self, znear: f32, zfar: f32) -> Self {
+        match *self {
+            Self::Perspective { fovy, .. } | Self::InfinitePerspective { fovy, .. } => {
+                Self::Perspective { fovy, znear, zfar }
+            }
+            Self::Orthographic { height, .. } => Self::Orthographic { height, znear, zfar },
+            Self::OffCenter { fovy, shift, .. } => Self::OffCenter { fovy, shift, znear, zfar },
+        }
+    }
+
+    /// Projection matrix producing wgpu clip space. Depth runs from 0 at the
+    /// near plane to 1 at the far plane, or the other way round with
+    /// `reverse_z`.
+    pub fn matrix(&self, aspect: f32, reverse_z: bool) -> cgmath::Matrix4<f32> {
+        let matrix = self.forward_matrix(aspect);
+        if reverse_z {
+            REVERSE_Z_MATRIX * matrix
+        } else {
+            matrix
+        }
+    }
+
+    fn forward_matrix(&self, aspect: f32) -> cgmath::Matrix4<f32> {
+        match *self {
+            Self::Perspective { fovy, znear, zfar } => {
+                OPENGL_TO_WGPU_MATRIX * cgmath::perspective(fovy, aspect, znear, zfar)
+            }
+            Self::Orthographic { height, znear, zfar } => {
+                let top = height / 2.0;
+                let right = top * aspect;
+                OPENGL_TO_WGPU_MATRIX * cgmath::ortho(-right, right, -top, top, znear, zfar)
+            }
+            Self::InfinitePerspective { fovy, znear } => {
+                let focal = 1.0 / (fovy / 2.0).tan();
+                // The limit of a perspective matrix as the far plane goes to
+                // infinity: depth is 1 - znear / distance. Reversed that
+                // becomes exactly znear / distance.
+                #[rustfmt::skip]
+                let matrix = cgmath::Matrix4::new(
+                    focal / aspect, 0.0, 0.0, 0.0,
+                    0.0, focal, 0.0, 0.0,
+                    0.0, 0.0, -1.0, -1.0,
+                    0.0, 0.0, -znear, 0.0,
+                );
+                matrix
+            }
+            Self::OffCenter { fovy, shift, znear, zfar } => {
+                let top = znear * (fovy / 2.0).tan();
+                let right = top * aspect;
+                OPENGL_TO_WGPU_MATRIX * cgmath::frustum(
+                    right * (shift.x - 1.0),
+                    right * (shift.x + 1.0),
+                    top * (shift.y - 1.0),
+                    top * (shift.y + 1.0),
+                    znear,
+                    zfar,
+                )
+            }
+        }
+    }
+}
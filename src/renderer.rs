@@ -0,0 +1,768 @@
+use anyhow::Context;
+use wgpu::util::DeviceExt;
+
+use crate::asset_cache::{AssetCache, Handle};
+use crate::model::{self, DrawHighlight, DrawLight, DrawModel, Vertex};
+use crate::light::{LightKind, LightList};
+use crate::id_buffer::{IdBuffer, PixelHit};
+use crate::bounds::Aabb;
+use crate::culling::{CullStats, CulledInstances, Culling};
+use crate::gpu_culling::{DrawIndexedIndirectArgs, GpuCuller};
+use crate::instances::InstanceList;
+use crate::picking::{self, InstanceHit};
+use crate::scene_graph::SceneGraph;
+use crate::shadow::{ShadowMap, ShadowSettings};
+use crate::{screenshot, texture, Camera, CameraUniform, Instance, InstanceRaw, Projection, Scene};
+
+/// Everything needed to draw the scene into a color target, independent of
+/// where that target comes from (a window surface or an offscreen texture).
+pub struct Renderer {
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub bg_color: wgpu::Color,
+    /// The active one of the scene's cameras.
+    pub camera: Camera,
+    // The scene's cameras as last left, with `camera` standing in for the
+    // active one
+    cameras: Vec<Camera>,
+    active_camera: usize,
+    pub instances: InstanceList,
+    /// Nodes whose models are kept in `instances` by [`Renderer::update`].
+    pub scene_graph: SceneGraph,
+    /// Instance drawn with an outline, usually set from [`Renderer::pick`].
+    pub selected_instance: Option<usize>,
+    /// How instances outside the camera's view are skipped in the main pass.
+    pub culling: Culling,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    pub lights: LightList,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    light_pipeline_layout: wgpu::PipelineLayout,
+    highlight_pipeline_layout: wgpu::PipelineLayout,
+    render_pipeline: wgpu::RenderPipeline,
+    light_render_pipeline: wgpu::RenderPipeline,
+    highlight_pipeline: wgpu::RenderPipeline,
+    // Depth direction the pipelines were built for
+    reverse_z: bool,
+    light_bind_group_layout: wgpu::BindGroupLayout,
+    shadow_map: ShadowMap,
+    id_buffer: IdBuffer,
+    camera_uniform: CameraUniform,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    depth_texture: texture::Texture,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    assets: AssetCache,
+    models: Vec<Handle<model::Model>>,
+    light_model: Handle<model::Model>,
+    culled: CulledInstances,
+    // Made on first use, so devices without compute shaders never build it
+    gpu_culler: Option<GpuCuller>,
+    // Whether the last update culled with `gpu_culler`
+    culled_on_gpu: bool,
+}
+
+impl Renderer {
+    pub async fn new(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Self> {
+        let texture_bind_group_layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: Some("texture_binding_group_layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // normal map
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // base color factor
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            }
+        );
+
+        let camera = Camera {
+            // position the camera one unit up and 2 units back
+            // +z is out of the screen
+            eye: (0.0, 1.0, 2.0).into(),
+            // have it look at the origin
+            target: (0.0, 0.0, 0.0).into(),
+            // which way is "up"
+            up: cgmath::Vector3::unit_y(),
+            aspect: width as f32 / height as f32,
+            projection: Projection::perspective(cgmath::Deg(45.0), 0.1, 100.0),
+            reverse_z: false,
+        };
+
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_proj(&camera);
+
+        let camera_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Camera Buffer"),
+                contents: bytemuck::cast_slice(&[camera_uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let camera_bind_group_layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }
+                ],
+                label: Some("camera_bind_group_layout"),
+            }
+        );
+
+        let camera_bind_group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                layout: &camera_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: camera_buffer.as_entire_binding(),
+                    }
+                ],
+                label: Some("camera_bind_group"),
+            }
+        );
+
+        let instances = InstanceList::new(&device, Vec::new());
+        let culled = CulledInstances::new(&device);
+
+        let depth_texture = texture::Texture::create_depth_texture(&device, width, height, camera.reverse_z, "depth_texture");
+
+        let bg_color = wgpu::Color {
+            r: 0.005,
+            g: 0.005,
+            b: 0.005,
+            a: 1.0,
+        };
+
+        let light_bind_group_layout = LightList::bind_group_layout(&device);
+        let lights = LightList::new(&device, &light_bind_group_layout);
+
+        let shadow_map = ShadowMap::new(&device, ShadowSettings::default());
+
+        let id_buffer = IdBuffer::new(&device, &camera_bind_group_layout, width, height);
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[
+                    &texture_bind_group_layout,
+                    &camera_bind_group_layout,
+                    &light_bind_group_layout,
+                    shadow_map.bind_group_layout(),
+                ],
+                push_constant_ranges: &[],
+            });
+
+        // Draws the marker at the light's position; no textures and no instances.
+        let light_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light Pipeline Layout"),
+            bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let highlight_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Highlight Pipeline Layout"),
+            bind_group_layouts: &[&camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let reverse_z = camera.reverse_z;
+        let (render_pipeline, light_render_pipeline, highlight_pipeline) = create_pipelines(
+            &device,
+            &render_pipeline_layout,
+            &light_pipeline_layout,
+            &highlight_pipeline_layout,
+            format,
+            reverse_z,
+        );
+
+        // Marks where the lights are, whatever the scene holds
+        let mut assets = AssetCache::new();
+        let light_model = assets.load_model("cube.obj", &device, &queue, &texture_bind_group_layout).await?;
+
+        Ok(Self {
+            device,
+            queue,
+            bg_color,
+            camera,
+            cameras: vec![camera],
+            active_camera: 0,
+            instances,
+            scene_graph: SceneGraph::new(),
+            selected_instance: None,
+            culling: Culling::Cpu,
+            format,
+            width,
+            height,
+            render_pipeline_layout,
+            light_pipeline_layout,
+            highlight_pipeline_layout,
+            render_pipeline,
+            light_render_pipeline,
+            highlight_pipeline,
+            reverse_z,
+            lights,
+            light_bind_group_layout,
+            shadow_map,
+            id_buffer,
+            camera_uniform,
+            camera_buffer,
+            camera_bind_group,
+            depth_texture,
+            texture_bind_group_layout,
+            assets,
+            models: Vec::new(),
+            light_model,
+            culled,
+            gpu_culler: None,
+            culled_on_gpu: false,
+        })
+    }
+
+    /// Replaces the models, instances, cameras, lights and background with
+    /// the ones `scene` describes. Leaves everything as it was if a model
+    /// fails to load or the active camera is missing. Models the old scene
+    /// shares with the new one are kept rather than loaded again, the others
+    /// are freed.
+    pub async fn load_scene(&mut self, scene: &Scene) -> anyhow::Result<()> {
+        let camera = *scene
+            .camera()
+            .with_context(|| format!("scene has no camera {}", scene.active_camera))?;
+        let mut models = Vec::with_capacity(scene.models.len());
+        for file_name in &scene.models {
+            let model = self.assets
+                .load_model(file_name, &self.device, &self.queue, &self.texture_bind_group_layout)
+                .await
+                .with_context(|| format!("cannot load model {}", file_name))?;
+            models.push(model);
+        }
+        self.models = models;
+
+        self.scene_graph = SceneGraph::new();
+        self.instances.clear();
+        for &instance in &scene.instances {
+            self.instances.push(instance);
+        }
+        self.selected_instance = None;
+        // The aspect ratio belongs to the render target, not the scene
+        self.camera = Camera { aspect: self.camera.aspect, ..camera };
+        self.cameras = scene.cameras.clone();
+        self.active_camera = scene.active_camera;
+        self.lights.clear();
+        for &light in &scene.lights {
+            self.lights.add(light);
+        }
+        let [r, g, b, a] = scene.clear_color;
+        self.bg_color = wgpu::Color { r, g, b, a };
+        Ok(())
+    }
+
+    /// The scene as it is now, for saving.
+    pub fn scene(&self) -> Scene {
+        Scene {
+            models: self.models.iter().map(|model| model.name.clone()).collect(),
+            instances: self.instances.iter().copied().collect(),
+            cameras: self.cameras(),
+            active_camera: self.active_camera,
+            lights: self.lights.iter().map(|(_, light)| *light).collect(),
+            clear_color: [self.bg_color.r, self.bg_color.g, self.bg_color.b, self.bg_color.a],
+        }
+    }
+
+    /// The scene's cameras, with the active one as it is now.
+    pub fn cameras(&self) -> Vec<Camera> {
+        let mut cameras = self.cameras.clone();
+        cameras[self.active_camera] = self.camera;
+        cameras
+    }
+
+    pub fn active_camera(&self) -> usize {
+        self.active_camera
+    }
+
+    /// Looks through the scene's camera at `index` instead. The camera
+    /// switched away from keeps any changes made to it.
+    pub fn switch_camera(&mut self, index: usize) -> anyhow::Result<()> {
+        anyhow::ensure!(index < self.cameras.len(), "no camera {} of {}", index, self.cameras.len());
+        self.cameras[self.active_camera] = self.camera;
+        self.camera = Camera { aspect: self.camera.aspect, ..self.cameras[index] };
+        self.active_camera = index;
+        Ok(())
+    }
+
+    /// The scene's models, in the order instances refer to them.
+    pub fn models(&self) -> &[Handle<model::Model>] {
+        &self.models
+    }
+
+    /// Where the models come from, for seeing how much GPU memory they take.
+    pub fn assets(&self) -> &AssetCache {
+        &self.assets
+    }
+
+    /// The color format the pipeline was built for.
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.width = width;
+            self.height = height;
+            self.camera.aspect = width as f32 / height as f32;
+            self.depth_texture = texture::Texture::create_depth_texture(&self.device, width, height, self.reverse_z, "depth_texture");
+            self.id_buffer.resize(&self.device, width, height);
+        }
+    }
+
+    /// Uploads the current camera state and any light, instance and scene
+    /// graph changes to the GPU.
+    pub fn update(&mut self) {
+        let reverse_z = self.camera.reverse_z;
+        if reverse_z != self.reverse_z {
+            self.depth_texture = texture::Texture::create_depth_texture(
+                &self.device,
+                self.width,
+                self.height,
+                reverse_z,
+                "depth_texture",
+            );
+            (self.render_pipeline, self.light_render_pipeline, self.highlight_pipeline) = create_pipelines(
+                &self.device,
+                &self.render_pipeline_layout,
+                &self.light_pipeline_layout,
+                &self.highlight_pipeline_layout,
+                self.format,
+                reverse_z,
+            );
+            self.reverse_z = reverse_z;
+        }
+
+        self.camera_uniform.update_view_proj(&self.camera);
+        self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+        self.lights.upload(&self.device, &self.queue, &self.light_bind_group_layout);
+        self.scene_graph.update(&mut self.instances);
+        let changed = self.instances.dirty();
+        self.instances.upload(&self.device, &self.queue);
+        if self.culling == Culling::Gpu && !self.gpu_culling_supported() {
+            log::warn!("GPU culling needs compute shaders, culling on the CPU instead");
+            self.culling = Culling::Cpu;
+        }
+        self.culled_on_gpu = false;
+        match self.culling {
+            Culling::Gpu => {
+                let gpu_culler = self.gpu_culler.get_or_insert_with(|| GpuCuller::new(&self.device));
+                gpu_culler.update(&self.device, &self.queue, &self.camera.frustum(), &self.instances, &self.models);
+                self.culled_on_gpu = true;
+                // Edits made meanwhile never reach the culled buffer
+                self.culled.invalidate();
+            }
+            _ => {
+                let frustum = (self.culling != Culling::Off).then(|| self.camera.frustum());
+                self.culled.update(
+                    &self.device,
+                    &self.queue,
+                    frustum.as_ref(),
+                    self.instances.as_slice(),
+                    &self.model_bounds(),
+                    changed,
+                );
+            }
+        }
+
+        // The first directional light casts the shadows
+        let shadow_light = self.lights
+            .iter()
+            .enumerate()
+            .find(|(_, (_, light))| light.kind == LightKind::Directional)
+            .map(|(index, (_, light))| (index, light.unit_direction()));
+        self.shadow_map.update(&self.queue, &self.camera, shadow_light);
+    }
+
+    /// The closest instance under the pixel at `x`, `y`, counted from the top
+    /// left of the render target.
+    pub fn pick(&self, x: f32, y: f32) -> Option<InstanceHit> {
+        let ray = self.camera.screen_ray(x, y, self.width as f32, self.height as f32);
+        picking::pick_instance(&ray, self.instances.as_slice(), &self.model_bounds())
+    }
+
+    fn model_bounds(&self) -> Vec<Option<Aabb>> {
+        self.models.iter().map(|model| model.bounds()).collect()
+    }
+
+    /// Selects the instance under the pixel at `x`, `y`, or clears the
+    /// selection when there is none, and returns the new selection.
+    pub fn select_at(&mut self, x: f32, y: f32) -> Option<usize> {
+        self.selected_instance = self.pick(x, y).map(|hit| hit.instance);
+        self.selected_instance
+    }
+
+    /// Removes the instance at `index`, keeping the selection and the scene
+    /// graph's instances pointing at the same instances as before. Instances
+    /// the scene graph owns have to be removed through the graph instead.
+    pub fn remove_instance(&mut self, index: usize) -> anyhow::Result<Instance> {
+        anyhow::ensure!(index < self.instances.len(), "no instance {}", index);
+        if let Some(node) = self.scene_graph.owner(index) {
+            anyhow::bail!("instance {} belongs to scene graph node {:?}", index, node);
+        }
+        let instance = self.instances.remove(index);
+        self.scene_graph.instance_removed(index);
+        self.selected_instance = match self.selected_instance {
+            Some(selected) if selected == index => None,
+            Some(selected) if selected > index => Some(selected - 1),
+            selected => selected,
+        };
+        Ok(instance)
+    }
+
+    /// Pixel exact picking: draws the instance and mesh IDs and reads back
+    /// the pixel at `x`, `y`. Unlike [`Renderer::pick`] this follows the
+    /// actual triangles, so it works for any mesh shape. Uses the camera as of
+    /// the last [`Renderer::update`] and waits for the GPU.
+    pub fn pick_pixel(&mut self, x: u32, y: u32) -> anyhow::Result<Option<PixelHit>> {
+        let runs = self.instances.draw_runs();
+        self.id_buffer.prepare(&self.device, &self.queue, &self.models, &runs);
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("ID Encoder"),
+        });
+        self.id_buffer.encode(
+            &mut encoder,
+            &self.models,
+            &self.camera_bind_group,
+            self.instances.buffer(),
+            &runs,
+            self.reverse_z,
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+        self.id_buffer.read_pixel(&self.device, &self.queue, x, y)
+    }
+
+    /// Whether the device can run [`Culling::Gpu`]. Without it, updating
+    /// switches [`Renderer::culling`] to [`Culling::Cpu`].
+    pub fn gpu_culling_supported(&self) -> bool {
+        GpuCuller::is_supported(&self.device)
+    }
+
+    // The culler whose results the next frame draws, if the last update used it
+    fn active_gpu_culler(&self) -> Option<&GpuCuller> {
+        self.gpu_culler.as_ref().filter(|_| self.culled_on_gpu)
+    }
+
+    /// Culling results from the last [`Renderer::update`]. After culling on
+    /// the GPU this waits for the GPU to finish and reads the count back.
+    pub fn cull_stats(&self) -> anyhow::Result<CullStats> {
+        if let Some(gpu_culler) = self.active_gpu_culler() {
+            gpu_culler.read_stats(&self.device, &self.queue)
+        } else {
+            Ok(self.culled.stats())
+        }
+    }
+
+    pub fn shadow_settings(&self) -> &ShadowSettings {
+        self.shadow_map.settings()
+    }
+
+    pub fn set_shadow_settings(&mut self, settings: ShadowSettings) {
+        self.shadow_map.set_settings(&self.device, settings);
+    }
+
+    /// Records the scene into `encoder`, drawing onto `view`.
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        if let Some(gpu_culler) = self.active_gpu_culler() {
+            gpu_culler.encode(encoder);
+        }
+        self.shadow_map.encode(encoder, &self.models, self.instances.buffer(), &self.instances.draw_runs());
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.bg_color),
+                        store: true,
+                    },
+                })
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    // The far plane: 0 when depth is reversed
+                    load: wgpu::LoadOp::Clear(if self.reverse_z { 0.0 } else { 1.0 }),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_pipeline(&self.light_render_pipeline);
+        // One marker per light; the shader hides directional ones.
+        render_pass.draw_light_model_instanced(
+            &self.light_model,
+            0..self.lights.len() as u32,
+            &self.camera_bind_group,
+            self.lights.bind_group(),
+        );
+
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(3, self.shadow_map.bind_group(), &[]);
+        if let Some(gpu_culler) = self.active_gpu_culler() {
+            let instance_size = std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress;
+            for run in gpu_culler.runs().iter().filter(|run| !run.draws.is_empty()) {
+                let Some(model) = self.models.get(run.model) else {
+                    continue;
+                };
+                // Each run's survivors start where its instances do
+                let visible = run.instances.start as wgpu::BufferAddress * instance_size
+                    ..run.instances.end as wgpu::BufferAddress * instance_size;
+                render_pass.set_vertex_buffer(1, gpu_culler.visible_buffer().slice(visible));
+                render_pass.draw_model_indirect(
+                    model,
+                    run.material.and_then(|material| model.materials.get(material)),
+                    gpu_culler.indirect_buffer(),
+                    run.draws.start as wgpu::BufferAddress * DrawIndexedIndirectArgs::SIZE,
+                    &self.camera_bind_group,
+                    self.lights.bind_group(),
+                );
+            }
+        } else {
+            render_pass.set_vertex_buffer(1, self.culled.buffer().slice(..));
+            for run in self.culled.draw_runs() {
+                let Some(model) = self.models.get(run.model) else {
+                    continue;
+                };
+                // Overrides pointing past the model's materials fall back to its own
+                match run.material.and_then(|material| model.materials.get(material)) {
+                    Some(material) => render_pass.draw_model_instanced_with_material(
+                        model,
+                        material,
+                        run.instances,
+                        &self.camera_bind_group,
+                        self.lights.bind_group(),
+                    ),
+                    None => render_pass.draw_model_instanced(model, run.instances, &self.camera_bind_group, self.lights.bind_group()),
+                }
+            }
+        }
+
+        let selected = self.selected_instance
+            .and_then(|index| Some((index, self.models.get(self.instances.get(index)?.model)?)));
+        if let Some((index, model)) = selected {
+            render_pass.set_pipeline(&self.highlight_pipeline);
+            // The selection indexes the full list, not the culled one
+            render_pass.set_vertex_buffer(1, self.instances.buffer().slice(..));
+            render_pass.draw_highlight_model(model, index as u32, &self.camera_bind_group);
+        }
+    }
+
+    /// Draws the scene onto `view` and submits the work to the queue.
+    pub fn render(&self, view: &wgpu::TextureView) {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Render Encoder"),
+        });
+        self.encode(&mut encoder, view);
+        self.queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    /// Renders the scene into an intermediate texture of the same format as
+    /// the real target and reads it back.
+    pub fn capture(&self) -> anyhow::Result<image::RgbaImage> {
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Capture Target"),
+            size: wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.render(&view);
+        screenshot::read_texture(&self.device, &self.queue, &texture)
+    }
+}
+
+/// Builds the model, light marker and highlight pipelines for the given depth
+/// direction.
+fn create_pipelines(
+    device: &wgpu::Device,
+    render_pipeline_layout: &wgpu::PipelineLayout,
+    light_pipeline_layout: &wgpu::PipelineLayout,
+    highlight_pipeline_layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    reverse_z: bool,
+) -> (wgpu::RenderPipeline, wgpu::RenderPipeline, wgpu::RenderPipeline) {
+    let depth_compare = if reverse_z { wgpu::CompareFunction::Greater } else { wgpu::CompareFunction::Less };
+
+    let render_pipeline = {
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Normal Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+        };
+        create_render_pipeline(
+            device,
+            render_pipeline_layout,
+            format,
+            Some((texture::Texture::DEPTH_FORMAT, depth_compare)),
+            &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            wgpu::Face::Back,
+            shader,
+        )
+    };
+
+    let light_render_pipeline = {
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Light Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("light.wgsl").into()),
+        };
+        create_render_pipeline(
+            device,
+            light_pipeline_layout,
+            format,
+            Some((texture::Texture::DEPTH_FORMAT, depth_compare)),
+            &[model::ModelVertex::desc()],
+            wgpu::Face::Back,
+            shader,
+        )
+    };
+
+    // Only the back faces of the enlarged model, so the instance drawn before
+    // stays visible inside its outline.
+    let highlight_pipeline = {
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Highlight Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("highlight.wgsl").into()),
+        };
+        create_render_pipeline(
+            device,
+            highlight_pipeline_layout,
+            format,
+            Some((texture::Texture::DEPTH_FORMAT, depth_compare)),
+            &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            wgpu::Face::Front,
+            shader,
+        )
+    };
+
+    (render_pipeline, light_render_pipeline, highlight_pipeline)
+}
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth: Option<(wgpu::TextureFormat, wgpu::CompareFunction)>,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    cull_mode: wgpu::Face,
+    shader: wgpu::ShaderModuleDescriptor,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(shader);
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",                 // vertex shader entrypoint
+            buffers: vertex_layouts,                // vertex types?
+        },
+        fragment: Some(wgpu::FragmentState {        //optional?
+            module: &shader,
+            entry_point: "fs_main",                 // fragment shader entrypoint
+            targets:                                // what color outputs to use
+                &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),     // just replace old pixel data
+                    write_mask: wgpu::ColorWrites::ALL,         // write to all color-channels
+                })],
+        }),
+        primitive: wgpu::PrimitiveState {                       // how to interpret vertices
+            topology: wgpu::PrimitiveTopology::TriangleList,    // every three vertices = 1 triangle
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,                   // if arranged counterclockwise triangle is facing forwards
+            cull_mode: Some(cull_mode),                         // exclude triangles facing away (usually backwards)
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: depth.map(|(format, depth_compare)| wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: true,
+            depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
@@ -1,21 +1,131 @@
 use std::io::{Cursor, BufReader};
+use std::path::Path;
 
-use anyhow::Ok;
+use anyhow::{Context, Ok};
+use base64::Engine;
+use cgmath::prelude::*;
 use wgpu::util::DeviceExt;
 
-use crate::{texture, model};
+use crate::bounds::Aabb;
+use crate::asset_cache::{AssetCache, Handle};
+use crate::{texture, model, vfs};
 
 
+/// Errors for model files that are readable but malformed or incomplete.
+///
+/// Loaders return these inside `anyhow::Error`; use `downcast_ref` to match
+/// on them.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The model references a material library that can't be read.
+    MissingMaterialLibrary { path: String },
+    /// A material references a texture that can't be read.
+    MissingTexture { material: String, path: String },
+    /// A face references a vertex that doesn't exist.
+    IndexOutOfRange { mesh: String, index: u32, vertex_count: usize },
+    /// The OBJ or MTL file couldn't be parsed.
+    Obj { file: String, error: tobj::LoadError },
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::MissingMaterialLibrary { path } => {
+                write!(f, "material library {:?} could not be loaded", path)
+            }
+            LoadError::MissingTexture { material, path } => {
+                write!(f, "texture {:?} of material {:?} could not be loaded", path, material)
+            }
+            LoadError::IndexOutOfRange { mesh, index, vertex_count } => {
+                write!(f, "mesh {:?} references vertex {} but only has {} vertices", mesh, index, vertex_count)
+            }
+            LoadError::Obj { file, error } => write!(f, "{:?} is malformed: {}", file, error),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Obj { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// How to fill in normals for meshes that don't provide any.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum NormalGeneration {
+    /// Average the normals of all faces sharing a vertex.
+    #[default]
+    Smooth,
+    /// Give every face its own vertices and its face normal.
+    Flat,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LoadOptions {
+    pub normals: NormalGeneration,
+}
+
+/// Loads a model, picking the format from the file extension: `.gltf` and
+/// `.glb` go through the glTF loader, everything else is read as Wavefront OBJ.
+///
+/// Materials naming the same image share one texture. To share them with
+/// other models too, load through an [`AssetCache`].
 pub async fn load_model(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Model> {
+    load_model_with_options(file_name, device, queue, layout, &LoadOptions::default()).await
+}
+
+pub async fn load_model_with_options(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    options: &LoadOptions,
+) -> anyhow::Result<model::Model> {
+    load_model_cached(file_name, device, queue, layout, options, &mut AssetCache::new()).await
+}
+
+/// [`load_model_with_options`] taking textures from `cache`.
+pub(crate) async fn load_model_cached(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    options: &LoadOptions,
+    cache: &mut AssetCache,
+) -> anyhow::Result<model::Model> {
+    let extension = std::path::Path::new(file_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("gltf") | Some("glb") => load_gltf(file_name, device, queue, layout, options, cache).await,
+        _ => load_obj(file_name, device, queue, layout, options, cache).await,
+    }
+}
+
+async fn load_obj(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    options: &LoadOptions,
+    cache: &mut AssetCache,
 ) -> anyhow::Result<model::Model> {
     let obj_text = load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
 
+    // tobj only reports a generic error for a failed material library, so
+    // remember which one it was.
+    let missing_mtl = std::rc::Rc::new(std::cell::RefCell::new(None));
     let (models, obj_materials) = tobj::load_obj_buf_async(
         &mut obj_reader, 
         &tobj::LoadOptions {
@@ -23,105 +133,580 @@ pub async fn load_model(
             single_index: true,
             ..Default::default()
         },
-        |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+        |p| {
+            let missing_mtl = missing_mtl.clone();
+            async move {
+                match load_string(&p).await {
+                    std::result::Result::Ok(mat_text) => {
+                        tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+                    }
+                    Err(_) => {
+                        *missing_mtl.borrow_mut() = Some(p);
+                        Err(tobj::LoadError::OpenFileFailed)
+                    }
+                }
+            }
         },
-    ).await?;
+    ).await.map_err(|error| LoadError::Obj { file: file_name.to_string(), error })?;
+
+    let obj_materials = match obj_materials {
+        std::result::Result::Ok(obj_materials) => obj_materials,
+        Err(error) => match missing_mtl.borrow_mut().take() {
+            Some(path) => return Err(LoadError::MissingMaterialLibrary { path }.into()),
+            None => return Err(LoadError::Obj { file: file_name.to_string(), error }.into()),
+        },
+    };
 
     let mut materials = Vec::new();
-    for m in obj_materials? {
-        let diffuse_texture = load_texture(&m.diffuse_texture, device, queue).await?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },        
-            ],
-            label: None,
-        });
-
-        materials.push(model::Material {
-            name: m.name,
+    for m in obj_materials {
+        let diffuse_texture = if m.diffuse_texture.is_empty() {
+            // No map_Kd: fall back to the plain diffuse color.
+            let [r, g, b] = m.diffuse;
+            Handle::new(texture::Texture::from_color(device, queue, linear_to_srgb8([r, g, b, 1.0]), &m.name, false)?)
+        } else {
+            load_material_texture(&m.name, &m.diffuse_texture, false, device, queue, cache).await?
+        };
+        let normal_texture = if m.normal_texture.is_empty() {
+            Handle::new(texture::Texture::from_color(device, queue, texture::Texture::FLAT_NORMAL, &m.name, true)?)
+        } else {
+            load_material_texture(&m.name, &m.normal_texture, true, device, queue, cache).await?
+        };
+        let metallic_roughness_texture = cache.white_texture(device, queue)?;
+        materials.push(model::Material::new(
+            device,
+            &m.name,
             diffuse_texture,
-            bind_group,
-        })
+            normal_texture,
+            metallic_roughness_texture,
+            model::MaterialFactors::default(),
+            layout,
+        ));
     }
 
-    let meshes = models
-        .into_iter()
-        .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3 )
-                .map(|i| model::ModelVertex {
-                    position: [
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ],
-                    tex_coords: [
+    let mut meshes = Vec::new();
+    let mut default_material = None;
+    for m in models {
+        let vertex_count = m.mesh.positions.len() / 3;
+        if let Some(&index) = m.mesh.indices.iter().find(|&&i| i as usize >= vertex_count) {
+            return Err(LoadError::IndexOutOfRange { mesh: m.name, index, vertex_count }.into());
+        }
+
+        let has_tex_coords = m.mesh.texcoords.len() >= vertex_count * 2;
+        let has_normals = m.mesh.normals.len() >= vertex_count * 3;
+        let mut vertices = (0..vertex_count)
+            .map(|i| model::ModelVertex {
+                position: [
+                    m.mesh.positions[i * 3],
+                    m.mesh.positions[i * 3 + 1],
+                    m.mesh.positions[i * 3 + 2],
+                ],
+                tex_coords: if has_tex_coords {
+                    [
                         m.mesh.texcoords[i * 2],
                         m.mesh.texcoords[i * 2 + 1],
-                    ],
-                    normal: [
+                    ]
+                } else {
+                    [0.0, 0.0]
+                },
+                normal: if has_normals {
+                    [
                         m.mesh.normals[i * 3],
                         m.mesh.normals[i * 3 + 1],
                         m.mesh.normals[i * 3 + 2],
-                    ],
-                }).collect::<Vec<_>>();
-
-                let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                    contents: bytemuck::cast_slice(&vertices),
-                    usage: wgpu::BufferUsages::VERTEX,
-                });
-
-                let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                    contents: bytemuck::cast_slice(&m.mesh.indices),
-                    usage: wgpu::BufferUsages::INDEX,
-                });
-
-                model::Mesh {
-                    name: file_name.to_string(),
-                    vertex_buffer,
-                    index_buffer,
-                    num_elements: m.mesh.indices.len() as u32,
-                    material: m.mesh.material_id.unwrap_or(0),
+                    ]
+                } else {
+                    [0.0, 0.0, 0.0]
+                },
+                // Filled in by compute_tangents
+                tangent: [0.0; 3],
+                bitangent: [0.0; 3],
+            }).collect::<Vec<_>>();
+        let mut indices = m.mesh.indices;
+
+        if !has_normals {
+            generate_normals(&mut vertices, &mut indices, options.normals);
+        }
+        compute_tangents(&mut vertices, &indices);
+
+        let material = match m.mesh.material_id {
+            Some(id) if id < materials.len() => id,
+            _ => *default_material.get_or_insert(materials.len()),
+        };
+        meshes.push(create_mesh(device, file_name, &vertices, &indices, material));
+    }
+
+    if default_material.is_some() {
+        materials.push(default_material_for(device, queue, layout, "Default Material", cache)?);
+    }
+
+    Ok(model::Model { name: file_name.to_string(), meshes, materials })
+}
+
+/// Plain white material for meshes that don't reference one.
+fn default_material_for(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    name: &str,
+    cache: &mut AssetCache,
+) -> anyhow::Result<model::Material> {
+    let diffuse_texture = texture::Texture::from_color(device, queue, [255; 4], name, false)?;
+    let normal_texture = texture::Texture::from_color(device, queue, texture::Texture::FLAT_NORMAL, name, true)?;
+    Ok(model::Material::new(
+        device,
+        name,
+        Handle::new(diffuse_texture),
+        Handle::new(normal_texture),
+        cache.white_texture(device, queue)?,
+        model::MaterialFactors::default(),
+        layout,
+    ))
+}
+
+async fn load_material_texture(
+    material: &str,
+    file_name: &str,
+    is_normal_map: bool,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    cache: &mut AssetCache,
+) -> anyhow::Result<Handle<texture::Texture>> {
+    if let Some(texture) = cache.cached_texture(file_name, is_normal_map) {
+        return Ok(texture);
+    }
+    let data = load_binary(file_name).await.map_err(|_| LoadError::MissingTexture {
+        material: material.to_string(),
+        path: file_name.to_string(),
+    })?;
+    let texture = texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map)?;
+    Ok(cache.insert_texture(file_name, is_normal_map, texture))
+}
+
+/// Fills in normals for an indexed triangle list, as the loaders do for
+/// meshes without any.
+///
+/// Flat normals need every face to have its own vertices, so in that mode the
+/// mesh is expanded and `indices` becomes `0..n`.
+pub fn generate_normals(vertices: &mut Vec<model::ModelVertex>, indices: &mut Vec<u32>, mode: NormalGeneration) {
+    let face_normal = |a: &model::ModelVertex, b: &model::ModelVertex, c: &model::ModelVertex| {
+        let a = cgmath::Vector3::from(a.position);
+        let b = cgmath::Vector3::from(b.position);
+        let c = cgmath::Vector3::from(c.position);
+        // Not normalized, so larger faces weigh more when smoothing.
+        (b - a).cross(c - a)
+    };
+
+    match mode {
+        NormalGeneration::Smooth => {
+            let mut normals = vec![cgmath::Vector3::zero(); vertices.len()];
+            for triangle in indices.chunks_exact(3) {
+                let [a, b, c] = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+                let normal = face_normal(&vertices[a], &vertices[b], &vertices[c]);
+                normals[a] += normal;
+                normals[b] += normal;
+                normals[c] += normal;
+            }
+            for (vertex, normal) in vertices.iter_mut().zip(normals) {
+                if normal.magnitude2() > 0.0 {
+                    vertex.normal = normal.normalize().into();
                 }
-        }).collect::<Vec<_>>();
+            }
+        }
+        NormalGeneration::Flat => {
+            let mut flat = Vec::with_capacity(indices.len());
+            for triangle in indices.chunks_exact(3) {
+                let mut corners = [vertices[triangle[0] as usize], vertices[triangle[1] as usize], vertices[triangle[2] as usize]];
+                let normal = face_normal(&corners[0], &corners[1], &corners[2]);
+                if normal.magnitude2() > 0.0 {
+                    for corner in &mut corners {
+                        corner.normal = normal.normalize().into();
+                    }
+                }
+                flat.extend_from_slice(&corners);
+            }
+            *indices = (0..flat.len() as u32).collect();
+            *vertices = flat;
+        }
+    }
+}
+
+/// Computes per-vertex tangents and bitangents from the texture coordinates,
+/// so normal maps can be sampled in tangent space.
+///
+/// Face tangents are accumulated per vertex and then made orthogonal to the
+/// vertex normal. Where the UVs give no direction (missing or degenerate
+/// texture coordinates) any vector perpendicular to the normal is used.
+fn compute_tangents(vertices: &mut [model::ModelVertex], indices: &[u32]) {
+    let mut tangents = vec![cgmath::Vector3::zero(); vertices.len()];
+    let mut bitangents = vec![cgmath::Vector3::zero(); vertices.len()];
 
-        Ok(model::Model { meshes, materials })
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+        let pos0 = cgmath::Vector3::from(vertices[a].position);
+        let pos1 = cgmath::Vector3::from(vertices[b].position);
+        let pos2 = cgmath::Vector3::from(vertices[c].position);
+        let uv0 = cgmath::Vector2::from(vertices[a].tex_coords);
+        let uv1 = cgmath::Vector2::from(vertices[b].tex_coords);
+        let uv2 = cgmath::Vector2::from(vertices[c].tex_coords);
+
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        // Solving
+        //     delta_pos1 = delta_uv1.x * T + delta_uv1.y * B
+        //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
+        let denominator = delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x;
+        if denominator.abs() < f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / denominator;
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+        // Flipped because wgpu's texture coordinates have y pointing down.
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+
+        for i in [a, b, c] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    for (vertex, (tangent, bitangent)) in vertices.iter_mut().zip(tangents.into_iter().zip(bitangents)) {
+        let (tangent, bitangent) = orthogonal_tangents(vertex.normal, tangent, bitangent);
+        vertex.tangent = tangent;
+        vertex.bitangent = bitangent;
+    }
+}
+
+/// Gram-Schmidt orthogonalizes `tangent` against `normal` and rebuilds the
+/// bitangent from both, keeping the handedness of the given `bitangent`.
+fn orthogonal_tangents(
+    normal: [f32; 3],
+    tangent: cgmath::Vector3<f32>,
+    bitangent: cgmath::Vector3<f32>,
+) -> ([f32; 3], [f32; 3]) {
+    let normal = cgmath::Vector3::from(normal);
+    let mut tangent = tangent - normal * normal.dot(tangent);
+    if tangent.magnitude2() < f32::EPSILON {
+        // Any vector not parallel to the normal will do.
+        let helper = if normal.x.abs() < 0.9 { cgmath::Vector3::unit_x() } else { cgmath::Vector3::unit_y() };
+        tangent = helper - normal * normal.dot(helper);
+    }
+    let tangent = tangent.normalize();
+    let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 { -1.0 } else { 1.0 };
+    (tangent.into(), (normal.cross(tangent) * handedness).into())
 }
 
+/// Reads a text asset, see [`load_binary`].
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
-    let path = std::path::Path::new(env!("OUT_DIR"))
-        .join("res")
-        .join(file_name);
-    let txt = std::fs::read_to_string(path)?;
+    let data = load_binary(file_name).await?;
+    let txt = String::from_utf8(data).with_context(|| format!("{} is not UTF-8 text", file_name))?;
 
     Ok(txt)
 }
 
+/// Reads a binary asset through the global [`vfs`], which without further
+/// setup looks in the [`asset_paths`](crate::asset_paths) and then, with the `embed-assets`
+/// feature, in the copy of `res/` compiled into the binary.
 pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
-    let path = std::path::Path::new(env!("OUT_DIR"))
-        .join("res")
-        .join(file_name);
-    let data = std::fs::read(path)?;
-    
-    Ok(data)
+    vfs::global().read(file_name)
 }
 
 pub async fn load_texture(
     file_name: &str,
+    is_normal_map: bool,
     device:&wgpu::Device,
     queue: &wgpu::Queue,
 ) -> anyhow::Result<texture::Texture> {
     let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name)
-}
\ No newline at end of file
+    texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
+}
+
+/// Loads a glTF 2.0 model, either a `.gltf` file with embedded (data URI) or
+/// external buffers, or a binary `.glb`.
+///
+/// Every primitive becomes its own mesh. Node transforms are baked into the
+/// vertices, so a mesh referenced by several nodes shows up once per node.
+///
+/// The metallic-roughness material is read in full, but only its base color
+/// texture and factor get used, since the renderer shades with Blinn-Phong.
+async fn load_gltf(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    options: &LoadOptions,
+    cache: &mut AssetCache,
+) -> anyhow::Result<model::Model> {
+    let data = load_binary(file_name).await?;
+    let gltf = gltf::Gltf::from_slice(&data)?;
+    let base_dir = Path::new(file_name).parent().unwrap_or_else(|| Path::new(""));
+
+    let mut buffers = Vec::new();
+    for buffer in gltf.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => gltf
+                .blob
+                .clone()
+                .context("glTF buffer refers to a missing GLB binary chunk")?,
+            gltf::buffer::Source::Uri(uri) => load_uri(uri, base_dir).await?,
+        };
+        buffers.push(data);
+    }
+
+    let sources = GltfSources {
+        buffers: &buffers,
+        base_dir,
+    };
+    let mut materials = Vec::new();
+    for material in gltf.materials() {
+        let pbr = material.pbr_metallic_roughness();
+        let name = material.name().unwrap_or("glTF Material");
+        let diffuse_texture = match pbr.base_color_texture() {
+            Some(info) => load_gltf_image(info.texture().source(), name, false, &sources, device, queue, cache).await?,
+            // The base color factor alone is the whole color
+            None => Handle::new(texture::Texture::from_color(device, queue, [255; 4], name, false)?),
+        };
+        let normal_texture = match material.normal_texture() {
+            Some(info) => load_gltf_image(info.texture().source(), name, true, &sources, device, queue, cache).await?,
+            None => Handle::new(texture::Texture::from_color(device, queue, texture::Texture::FLAT_NORMAL, name, true)?),
+        };
+        // Linear data, like normal maps
+        let metallic_roughness_texture = match pbr.metallic_roughness_texture() {
+            Some(info) => load_gltf_image(info.texture().source(), name, true, &sources, device, queue, cache).await?,
+            None => cache.white_texture(device, queue)?,
+        };
+        let factors = model::MaterialFactors {
+            base_color: pbr.base_color_factor(),
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+        };
+        materials.push(model::Material::new(
+            device,
+            name,
+            diffuse_texture,
+            normal_texture,
+            metallic_roughness_texture,
+            factors,
+            layout,
+        ));
+    }
+
+    let roots = match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+        Some(scene) => scene.nodes().collect::<Vec<_>>(),
+        None => {
+            // No scene: treat every node that isn't somebody's child as a root.
+            let children = gltf
+                .nodes()
+                .flat_map(|n| n.children().map(|c| c.index()))
+                .collect::<std::collections::HashSet<_>>();
+            gltf.nodes().filter(|n| !children.contains(&n.index())).collect()
+        }
+    };
+
+    let mut default_material = None;
+    let mut meshes = Vec::new();
+    let mut stack = roots
+        .into_iter()
+        .map(|node| (node, cgmath::Matrix4::identity()))
+        .collect::<Vec<_>>();
+    while let Some((node, parent_transform)) = stack.pop() {
+        let transform = parent_transform * cgmath::Matrix4::from(node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            let name = mesh.name().or(node.name()).unwrap_or(file_name);
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    log::warn!("{}: skipping primitive with unsupported mode {:?}", name, primitive.mode());
+                    continue;
+                }
+
+                let material = match primitive.material().index() {
+                    Some(index) => index,
+                    None => *default_material.get_or_insert(materials.len()),
+                };
+                let (mut vertices, mut indices) = read_gltf_primitive(&primitive, &buffers, transform, name)?;
+                if primitive.get(&gltf::Semantic::Normals).is_none() {
+                    generate_normals(&mut vertices, &mut indices, options.normals);
+                }
+                // Supplied tangents are only usable alongside supplied normals.
+                if primitive.get(&gltf::Semantic::Tangents).is_none() || primitive.get(&gltf::Semantic::Normals).is_none() {
+                    compute_tangents(&mut vertices, &indices);
+                }
+                meshes.push(create_mesh(device, name, &vertices, &indices, material));
+            }
+        }
+
+        for child in node.children() {
+            stack.push((child, transform));
+        }
+    }
+
+    if default_material.is_some() {
+        // glTF's default material is plain white too.
+        materials.push(default_material_for(device, queue, layout, "glTF Default Material", cache)?);
+    }
+
+    Ok(model::Model { name: file_name.to_string(), meshes, materials })
+}
+
+fn read_gltf_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[Vec<u8>],
+    transform: cgmath::Matrix4<f32>,
+    name: &str,
+) -> anyhow::Result<(Vec<model::ModelVertex>, Vec<u32>)> {
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+
+    let positions = reader
+        .read_positions()
+        .context("glTF primitive has no positions")?
+        .collect::<Vec<_>>();
+    let normals = reader.read_normals().map(|n| n.collect::<Vec<_>>());
+    let tex_coords = reader.read_tex_coords(0).map(|t| t.into_f32().collect::<Vec<_>>());
+    // xyz is the tangent, w the handedness of the bitangent
+    let tangents = reader.read_tangents().map(|t| t.collect::<Vec<_>>());
+    let mut indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    if let Some(&index) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+        return Err(LoadError::IndexOutOfRange { mesh: name.to_string(), index, vertex_count: positions.len() }.into());
+    }
+
+    // Normals transform with the inverse transpose of the model matrix.
+    let linear = cgmath::Matrix3::from_cols(transform.x.truncate(), transform.y.truncate(), transform.z.truncate());
+    let normal_matrix = linear.invert().unwrap_or(linear).transpose();
+
+    let vertices = positions
+        .iter()
+        .enumerate()
+        .map(|(i, position)| {
+            let normal = normals.as_ref().and_then(|n| n.get(i)).map_or(cgmath::Vector3::zero(), |n| {
+                (normal_matrix * cgmath::Vector3::from(*n)).normalize()
+            });
+            let (tangent, bitangent) = match tangents.as_ref().and_then(|t| t.get(i)) {
+                Some(&[x, y, z, w]) => {
+                    let tangent = transform.transform_vector(cgmath::Vector3::new(x, y, z));
+                    orthogonal_tangents(normal.into(), tangent, normal.cross(tangent) * w)
+                }
+                None => ([0.0; 3], [0.0; 3]),
+            };
+            model::ModelVertex {
+                position: transform.transform_point(cgmath::Point3::from(*position)).into(),
+                tex_coords: tex_coords.as_ref().and_then(|t| t.get(i)).copied().unwrap_or([0.0; 2]),
+                normal: normal.into(),
+                tangent,
+                bitangent,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // A mirroring transform flips the winding order, which would make the
+    // front faces get culled.
+    if linear.determinant() < 0.0 {
+        for triangle in indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+
+    Ok((vertices, indices))
+}
+
+/// What a glTF file's images are read from besides their own URIs.
+struct GltfSources<'a> {
+    buffers: &'a [Vec<u8>],
+    base_dir: &'a Path,
+}
+
+async fn load_gltf_image(
+    image: gltf::Image<'_>,
+    material: &str,
+    is_normal_map: bool,
+    sources: &GltfSources<'_>,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    cache: &mut AssetCache,
+) -> anyhow::Result<Handle<texture::Texture>> {
+    let label = image.name().unwrap_or("glTF Image");
+    match image.source() {
+        gltf::image::Source::View { view, .. } => {
+            let buffer = &sources.buffers[view.buffer().index()];
+            let bytes = buffer
+                .get(view.offset()..view.offset() + view.length())
+                .context("glTF image view is out of range")?;
+            Ok(Handle::new(texture::Texture::from_bytes(device, queue, bytes, label, is_normal_map)?))
+        }
+        gltf::image::Source::Uri { uri, .. } => {
+            // Data URIs are part of the glTF file, so only files can be shared
+            let path = (!uri.starts_with("data:")).then(|| sources.base_dir.join(uri).to_string_lossy().into_owned());
+            if let Some(texture) = path.as_deref().and_then(|path| cache.cached_texture(path, is_normal_map)) {
+                return Ok(texture);
+            }
+            let bytes = load_uri(uri, sources.base_dir).await.map_err(|_| LoadError::MissingTexture {
+                material: material.to_string(),
+                path: uri.to_string(),
+            })?;
+            let texture = texture::Texture::from_bytes(device, queue, &bytes, label, is_normal_map)?;
+            Ok(match path {
+                Some(path) => cache.insert_texture(&path, is_normal_map, texture),
+                None => Handle::new(texture),
+            })
+        }
+    }
+}
+
+/// Resolves a glTF URI, which is either a base64 data URI or a path relative
+/// to the glTF file.
+async fn load_uri(uri: &str, base_dir: &Path) -> anyhow::Result<Vec<u8>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data
+            .split_once(";base64,")
+            .with_context(|| format!("unsupported data URI {:?}", uri))?;
+        Ok(base64::engine::general_purpose::STANDARD.decode(encoded)?)
+    } else {
+        load_binary(&base_dir.join(uri).to_string_lossy()).await
+    }
+}
+
+fn linear_to_srgb8(color: [f32; 4]) -> [u8; 4] {
+    let encode = |c: f32| {
+        let c = c.clamp(0.0, 1.0);
+        let srgb = if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+        (srgb * 255.0).round() as u8
+    };
+    [encode(color[0]), encode(color[1]), encode(color[2]), (color[3].clamp(0.0, 1.0) * 255.0).round() as u8]
+}
+
+fn create_mesh(
+    device: &wgpu::Device,
+    name: &str,
+    vertices: &[model::ModelVertex],
+    indices: &[u32],
+    material: usize,
+) -> model::Mesh {
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Vertex Buffer", name)),
+        contents: bytemuck::cast_slice(vertices),
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Index Buffer", name)),
+        contents: bytemuck::cast_slice(indices),
+        usage: wgpu::BufferUsages::INDEX,
+    });
+
+    let bounds = Aabb::from_points(vertices.iter().map(|v| v.position.into()))
+        .unwrap_or_else(|| Aabb::new(cgmath::Point3::origin(), cgmath::Point3::origin()));
+
+    model::Mesh {
+        name: name.to_string(),
+        vertex_buffer,
+        index_buffer,
+        num_elements: indices.len() as u32,
+        material,
+        bounds,
+    }
+}
@@ -0,0 +1,121 @@
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::light::Light;
+use crate::{resources, Camera, Instance};
+
+/// Scene shown when no other is given, in the resource directory.
+pub const DEFAULT_SCENE: &str = "scenes/default.ron";
+
+/// Everything that makes up what the [`Renderer`](crate::Renderer) draws:
+/// the models, where their instances go, the cameras, the lights and the
+/// background. Stored as RON, see `res/scenes/default.ron`.
+///
+/// Files may give a single `camera` instead of `cameras` and
+/// `active_camera`. Loading fails without any camera, or with
+/// `active_camera` past the end.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "SceneFile")]
+pub struct Scene {
+    /// Model files in the resource directory. Instances refer to them by
+    /// index.
+    pub models: Vec<String>,
+    #[serde(default)]
+    pub instances: Vec<Instance>,
+    /// Viewpoints to look at the scene from, at least one.
+    pub cameras: Vec<Camera>,
+    /// Index into `cameras` of the one to start with.
+    pub active_camera: usize,
+    pub lights: Vec<Light>,
+    /// Linear RGBA the frame is cleared to.
+    pub clear_color: [f64; 4],
+}
+
+// What scene files hold, before checking the cameras
+#[derive(Deserialize)]
+struct SceneFile {
+    models: Vec<String>,
+    #[serde(default)]
+    instances: Vec<Instance>,
+    // Written as a plain camera rather than RON's `Some(...)`
+    #[serde(default, deserialize_with = "some")]
+    camera: Option<Camera>,
+    #[serde(default)]
+    cameras: Vec<Camera>,
+    #[serde(default)]
+    active_camera: usize,
+    #[serde(default)]
+    lights: Vec<Light>,
+    #[serde(default = "default_clear_color")]
+    clear_color: [f64; 4],
+}
+
+fn some<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Camera>, D::Error> {
+    Camera::deserialize(deserializer).map(Some)
+}
+
+impl TryFrom<SceneFile> for Scene {
+    type Error = String;
+
+    fn try_from(file: SceneFile) -> Result<Self, Self::Error> {
+        let cameras = match (file.camera, file.cameras.is_empty()) {
+            (Some(camera), true) => vec![camera],
+            (None, false) => file.cameras,
+            (Some(_), false) => return Err("scene has both `camera` and `cameras`".to_string()),
+            (None, true) => return Err("scene has no camera".to_string()),
+        };
+        if file.active_camera >= cameras.len() {
+            return Err(format!("active camera {} out of {} cameras", file.active_camera, cameras.len()));
+        }
+        Ok(Self {
+            models: file.models,
+            instances: file.instances,
+            cameras,
+            active_camera: file.active_camera,
+            lights: file.lights,
+            clear_color: file.clear_color,
+        })
+    }
+}
+
+fn default_clear_color() -> [f64; 4] {
+    [0.0, 0.0, 0.0, 1.0]
+}
+
+impl Scene {
+    /// The camera `active_camera` picks, or `None` if it's out of range,
+    /// which only a scene built by hand can be.
+    pub fn camera(&self) -> Option<&Camera> {
+        self.cameras.get(self.active_camera)
+    }
+
+    pub fn from_ron(text: &str) -> anyhow::Result<Self> {
+        Ok(ron::from_str(text)?)
+    }
+
+    /// Pretty printed with one instance or light per line.
+    pub fn to_ron(&self) -> anyhow::Result<String> {
+        let config = ron::ser::PrettyConfig::new().depth_limit(2);
+        Ok(ron::ser::to_string_pretty(self, config)?)
+    }
+
+    /// Reads a scene file from anywhere on disk.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("cannot read scene {}", path.display()))?;
+        Self::from_ron(&text).with_context(|| format!("invalid scene {}", path.display()))
+    }
+
+    /// Reads a scene file from the resource directory.
+    pub async fn load_resource(file_name: &str) -> anyhow::Result<Self> {
+        let text = resources::load_string(file_name).await?;
+        Self::from_ron(&text).with_context(|| format!("invalid scene {}", file_name))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_ron()?).with_context(|| format!("cannot write scene {}", path.display()))
+    }
+}
@@ -0,0 +1,419 @@
+use anyhow::Context;
+use cgmath::prelude::*;
+
+use crate::instances::InstanceList;
+use crate::Instance;
+
+/// Position, rotation and scale of a node relative to its parent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: cgmath::Vector3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::from_translation(cgmath::Vector3::zero())
+    }
+}
+
+impl Transform {
+    pub fn from_translation(translation: cgmath::Vector3<f32>) -> Self {
+        Self {
+            translation,
+            rotation: cgmath::Quaternion::one(),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Scales first, then rotates, then translates, like [`Instance`].
+    pub fn matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.translation)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+/// A model drawn at a node's world transform.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModelAttachment {
+    /// Index into the renderer's models.
+    pub model: usize,
+    /// See [`Instance::material`].
+    pub material: Option<usize>,
+    /// See [`Instance::color`].
+    pub color: [f32; 4],
+}
+
+impl ModelAttachment {
+    /// The model with its own materials and no tint.
+    pub fn new(model: usize) -> Self {
+        Self {
+            model,
+            material: None,
+            color: [1.0; 4],
+        }
+    }
+}
+
+/// Handle to a node. Stays invalid once the node is removed, even if its slot
+/// gets reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: usize,
+    generation: u32,
+}
+
+struct Node {
+    local: Transform,
+    world: cgmath::Matrix4<f32>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    attachment: Option<ModelAttachment>,
+    // Index into the instance list once the attachment has been added to it
+    instance: Option<usize>,
+    // The local transform changed since the world matrix was computed
+    dirty: bool,
+    // The world matrix or attachment changed since the instance was written
+    instance_dirty: bool,
+}
+
+struct Slot {
+    generation: u32,
+    node: Option<Node>,
+}
+
+/// Nodes with transforms relative to their parents, some of them carrying a
+/// model.
+///
+/// Edits only flag the nodes they touch. [`SceneGraph::update`] then
+/// recomputes the world matrices below those nodes and writes the instances
+/// of the attached models that moved, so the [`InstanceList`] uploads just
+/// those. The graph owns the instances it adds: remove them through the
+/// graph, and tell it with [`SceneGraph::instance_removed`] when removing
+/// other instances from the list, as that shifts the indices of its own.
+///
+/// Instances can't express shear, which a non-uniformly scaled parent with a
+/// rotated child produces, so that part of such world transforms is lost.
+#[derive(Default)]
+pub struct SceneGraph {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    roots: Vec<NodeId>,
+    // Some node is flagged, so `update_world` has work to do
+    dirty: bool,
+    // Instances of removed attachments, still in the instance list
+    removed_instances: Vec<usize>,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn node(&self, id: NodeId) -> Option<&Node> {
+        self.slots
+            .get(id.index)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_ref())
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.slots
+            .get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_mut())
+    }
+
+    fn expect_node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.node_mut(id).unwrap_or_else(|| panic!("no node {:?}", id))
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.node(id).is_some()
+    }
+
+    /// Adds a node under `parent`, or as a root for `None`.
+    ///
+    /// # Panics
+    ///
+    /// If `parent` is not in the graph.
+    pub fn add(&mut self, parent: Option<NodeId>, local: Transform) -> NodeId {
+        let node = Node {
+            local,
+            world: cgmath::Matrix4::identity(),
+            parent,
+            children: Vec::new(),
+            attachment: None,
+            instance: None,
+            dirty: true,
+            instance_dirty: false,
+        };
+        let id = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.node = Some(node);
+                NodeId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    node: Some(node),
+                });
+                NodeId {
+                    index: self.slots.len() - 1,
+                    generation: 0,
+                }
+            }
+        };
+        match parent {
+            Some(parent) => self.expect_node_mut(parent).children.push(id),
+            None => self.roots.push(id),
+        }
+        self.dirty = true;
+        id
+    }
+
+    /// Removes the node together with everything below it.
+    ///
+    /// # Panics
+    ///
+    /// If `id` is not in the graph.
+    pub fn remove(&mut self, id: NodeId) {
+        let parent = self.expect_node_mut(id).parent;
+        self.siblings_mut(parent).retain(|&sibling| sibling != id);
+
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            let slot = &mut self.slots[id.index];
+            let Some(node) = slot.node.take() else {
+                continue;
+            };
+            slot.generation = slot.generation.wrapping_add(1);
+            self.free.push(id.index);
+            self.removed_instances.extend(node.instance);
+            stack.extend(node.children);
+        }
+    }
+
+    /// Removes every node.
+    pub fn clear(&mut self) {
+        while let Some(&root) = self.roots.last() {
+            self.remove(root);
+        }
+    }
+
+    fn siblings_mut(&mut self, parent: Option<NodeId>) -> &mut Vec<NodeId> {
+        match parent {
+            Some(parent) => &mut self.expect_node_mut(parent).children,
+            None => &mut self.roots,
+        }
+    }
+
+    /// Moves the node and everything below it under `parent`, or makes it a
+    /// root for `None`. The local transform stays, so the node moves along
+    /// with its new parent.
+    ///
+    /// Fails without changing anything if `id` or `parent` is not in the
+    /// graph, or `parent` is `id` or below it.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> anyhow::Result<()> {
+        anyhow::ensure!(self.contains(id), "no node {:?}", id);
+        let mut ancestor = parent;
+        while let Some(node) = ancestor {
+            if node == id {
+                anyhow::bail!("cannot move node {:?} below itself", id);
+            }
+            ancestor = self.node(node).with_context(|| format!("no node {:?}", node))?.parent;
+        }
+
+        let old_parent = self.expect_node_mut(id).parent;
+        self.siblings_mut(old_parent).retain(|&sibling| sibling != id);
+        self.siblings_mut(parent).push(id);
+        let node = self.expect_node_mut(id);
+        node.parent = parent;
+        node.dirty = true;
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).and_then(|node| node.parent)
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        self.node(id).map_or(&[], |node| &node.children)
+    }
+
+    /// Nodes without a parent, in the order they were added.
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    pub fn local(&self, id: NodeId) -> Option<&Transform> {
+        self.node(id).map(|node| &node.local)
+    }
+
+    /// Flags the node as moved, so only call this when actually editing.
+    pub fn local_mut(&mut self, id: NodeId) -> Option<&mut Transform> {
+        if !self.contains(id) {
+            return None;
+        }
+        self.dirty = true;
+        let node = self.node_mut(id)?;
+        node.dirty = true;
+        Some(&mut node.local)
+    }
+
+    /// # Panics
+    ///
+    /// If `id` is not in the graph.
+    pub fn set_local(&mut self, id: NodeId, local: Transform) {
+        let node = self.expect_node_mut(id);
+        node.local = local;
+        node.dirty = true;
+        self.dirty = true;
+    }
+
+    /// The node's transform relative to the world as of the last
+    /// [`SceneGraph::update_world`].
+    pub fn world(&self, id: NodeId) -> Option<cgmath::Matrix4<f32>> {
+        self.node(id).map(|node| node.world)
+    }
+
+    /// Draws the model at the node, replacing any model already there.
+    ///
+    /// # Panics
+    ///
+    /// If `id` is not in the graph.
+    pub fn attach(&mut self, id: NodeId, attachment: ModelAttachment) {
+        let node = self.expect_node_mut(id);
+        node.attachment = Some(attachment);
+        node.instance_dirty = true;
+    }
+
+    /// Stops drawing a model at the node and returns it.
+    pub fn detach(&mut self, id: NodeId) -> Option<ModelAttachment> {
+        let node = self.node_mut(id)?;
+        let attachment = node.attachment.take();
+        let instance = node.instance.take();
+        self.removed_instances.extend(instance);
+        attachment
+    }
+
+    pub fn attachment(&self, id: NodeId) -> Option<&ModelAttachment> {
+        self.node(id).and_then(|node| node.attachment.as_ref())
+    }
+
+    /// Index of the node's model in the instance list as of the last
+    /// [`SceneGraph::update`], for matching picks and selections to nodes.
+    pub fn instance(&self, id: NodeId) -> Option<usize> {
+        self.node(id).and_then(|node| node.instance)
+    }
+
+    /// The node whose model is drawn by the instance at `index`.
+    pub fn owner(&self, index: usize) -> Option<NodeId> {
+        self.slots.iter().enumerate().find_map(|(slot_index, slot)| {
+            (slot.node.as_ref()?.instance == Some(index)).then_some(NodeId {
+                index: slot_index,
+                generation: slot.generation,
+            })
+        })
+    }
+
+    /// Moves the graph's instances after `index` down one, for an instance
+    /// removed from the list by something other than the graph.
+    pub fn instance_removed(&mut self, index: usize) {
+        let shift = |instance: &mut usize| {
+            if *instance > index {
+                *instance -= 1;
+            }
+        };
+        for node in self.slots.iter_mut().filter_map(|slot| slot.node.as_mut()) {
+            node.instance.as_mut().map(shift);
+        }
+        self.removed_instances.iter_mut().for_each(shift);
+    }
+
+    /// Recomputes the world matrices of moved nodes and everything below
+    /// them.
+    pub fn update_world(&mut self) {
+        if !std::mem::take(&mut self.dirty) {
+            return;
+        }
+        let mut stack = self
+            .roots
+            .iter()
+            .map(|&root| (root, cgmath::Matrix4::identity(), false))
+            .collect::<Vec<_>>();
+        while let Some((id, parent_world, parent_moved)) = stack.pop() {
+            let node = self.slots[id.index].node.as_mut().expect("children are in the graph");
+            let moved = parent_moved || node.dirty;
+            if moved {
+                node.world = parent_world * node.local.matrix();
+                node.dirty = false;
+                node.instance_dirty = true;
+            }
+            let world = node.world;
+            stack.extend(node.children.iter().map(|&child| (child, world, moved)));
+        }
+    }
+
+    /// Brings `instances` in line with the graph: removes the instances of
+    /// removed models, adds the ones of new models and rewrites the ones
+    /// that moved or changed.
+    pub fn update(&mut self, instances: &mut InstanceList) {
+        let mut removed_instances = std::mem::take(&mut self.removed_instances);
+        removed_instances.sort_unstable();
+        for &removed in removed_instances.iter().rev() {
+            instances.remove(removed);
+            self.instance_removed(removed);
+        }
+
+        self.update_world();
+
+        for node in self.slots.iter_mut().filter_map(|slot| slot.node.as_mut()) {
+            let Some(attachment) = node.attachment else {
+                continue;
+            };
+            if !std::mem::take(&mut node.instance_dirty) {
+                continue;
+            }
+            let instance = instance_at(&node.world, attachment);
+            match node.instance.and_then(|index| instances.get_mut(index)) {
+                Some(existing) => *existing = instance,
+                None => node.instance = Some(instances.push(instance)),
+            }
+        }
+    }
+}
+
+// Splits `world` back into an instance's translation, rotation and scale
+fn instance_at(world: &cgmath::Matrix4<f32>, attachment: ModelAttachment) -> Instance {
+    let mut axes = [world.x.truncate(), world.y.truncate(), world.z.truncate()];
+    let mut scale = axes.map(|axis| axis.magnitude());
+    for (axis, (length, unit)) in axes.iter_mut().zip(scale.iter().zip([
+        cgmath::Vector3::unit_x(),
+        cgmath::Vector3::unit_y(),
+        cgmath::Vector3::unit_z(),
+    ])) {
+        *axis = if *length > f32::EPSILON { *axis / *length } else { unit };
+    }
+    // A mirroring transform can't be a rotation, so flip one axis back
+    if axes[0].cross(axes[1]).dot(axes[2]) < 0.0 {
+        axes[0] = -axes[0];
+        scale[0] = -scale[0];
+    }
+    let rotation = cgmath::Quaternion::from(cgmath::Matrix3::from_cols(axes[0], axes[1], axes[2])).normalize();
+    Instance {
+        position: world.w.truncate(),
+        rotation,
+        scale: scale.into(),
+        color: attachment.color,
+        material: attachment.material,
+        model: attachment.model,
+    }
+}
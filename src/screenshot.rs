@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+
+/// Rows copied out of a texture must start on a multiple of
+/// `COPY_BYTES_PER_ROW_ALIGNMENT`, so each row is padded up to it.
+pub fn padded_bytes_per_row(width: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (4 * width).div_ceil(align) * align
+}
+
+/// Copies a texture back to the CPU as an RGBA image.
+///
+/// The texture needs `COPY_SRC` usage and one of the 8-bit RGBA/BGRA formats.
+/// The bytes are kept as stored: for sRGB formats they are already sRGB
+/// encoded, and for linear formats they are exactly what a display would
+/// show, so only the channel order needs fixing.
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> anyhow::Result<image::RgbaImage> {
+    let format = texture.format();
+    let swap_red_blue = match format {
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+        _ => bail!("cannot read back textures of format {:?}", format),
+    };
+
+    let size = texture.size();
+    let (width, height) = (size.width, size.height);
+    let unpadded_bytes_per_row = 4 * width;
+    let padded_bytes_per_row = padded_bytes_per_row(width);
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            aspect: wgpu::TextureAspect::All,
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                rows_per_image: std::num::NonZeroU32::new(height),
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let (tx, rx) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        tx.send(result).ok();
+    });
+    device.poll(wgpu::Maintain::Wait);
+    rx.recv()??;
+
+    let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+    {
+        let data = slice.get_mapped_range();
+        for row in data.chunks(padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+    }
+    buffer.unmap();
+
+    if swap_red_blue {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+
+    image::RgbaImage::from_raw(width, height, pixels).context("readback buffer has the wrong size")
+}
+
+/// Writes `image` to `dir` as `screenshot-<unix millis>.png`, creating the
+/// directory if needed, and returns the path of the new file.
+pub fn save_timestamped(image: &image::RgbaImage, dir: &Path) -> anyhow::Result<PathBuf> {
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_millis();
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("screenshot-{}.png", millis));
+    image.save(&path)?;
+    Ok(path)
+}
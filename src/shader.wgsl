@@ -5,22 +5,57 @@ struct InstanceInput {
     @location(6) model_matrix_1: vec4<f32>,
     @location(7) model_matrix_2: vec4<f32>,
     @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+    @location(12) color: vec4<f32>,
 }
 
 struct CameraUniform {
+    view_pos: vec4<f32>,
     view_proj: mat4x4<f32>,
 };
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
 
+struct Light {
+    position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    attenuation: vec3<f32>,
+    cos_inner: f32,
+    cos_outer: f32,
+}
+struct Lights {
+    count: u32,
+    lights: array<Light>,
+}
+@group(2) @binding(0)
+var<storage, read> lights: Lights;
+
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_DIRECTIONAL: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_tangent: vec3<f32>,
+    @location(3) world_bitangent: vec3<f32>,
+    @location(4) world_position: vec3<f32>,
+    @location(5) color: vec4<f32>,
 };
 
 @vertex
@@ -34,9 +69,22 @@ fn vs_main(
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);  // order of operators important.
+    // Tangents lie in the surface, so they move with the model matrix while
+    // the normal needs the normal matrix.
+    out.world_normal = normal_matrix * model.normal;
+    out.world_tangent = (model_matrix * vec4<f32>(model.tangent, 0.0)).xyz;
+    out.world_bitangent = (model_matrix * vec4<f32>(model.bitangent, 0.0)).xyz;
+    out.world_position = world_position.xyz;
+    out.color = instance.color;
+    out.clip_position = camera.view_proj * world_position;  // order of operators important.
     return out;
 }
 
@@ -46,9 +94,140 @@ fn vs_main(
 var t_diffuse: texture_2d<f32>;
 @group(0) @binding(1)
 var s_diffuse: sampler;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+@group(0) @binding(4)
+var<uniform> base_color_factor: vec4<f32>;
+
+struct Shadow {
+    cascades: array<mat4x4<f32>, 4>,
+    splits: vec4<f32>,
+    camera_forward: vec3<f32>,
+    cascade_count: u32,
+    camera_position: vec3<f32>,
+    light_index: i32,
+    depth_bias: f32,
+    normal_bias: f32,
+    pcf_radius: i32,
+    texel_size: f32,
+}
+@group(3) @binding(0)
+var t_shadow: texture_depth_2d_array;
+@group(3) @binding(1)
+var s_shadow: sampler_comparison;
+@group(3) @binding(2)
+var<uniform> shadow: Shadow;
 
 
-@fragment                    //store in first color target 
+@fragment                    //store in first color target
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
+    let object_color = textureSample(t_diffuse, s_diffuse, in.tex_coords) * base_color_factor * in.color;
+    let object_normal = textureSample(t_normal, s_normal, in.tex_coords);
+
+    // Move the sampled normal from tangent space into world space.
+    let tangent_matrix = mat3x3<f32>(
+        normalize(in.world_tangent),
+        normalize(in.world_bitangent),
+        normalize(in.world_normal),
+    );
+    let normal = normalize(tangent_matrix * (object_normal.xyz * 2.0 - 1.0));
+
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+
+    var lighting = vec3<f32>(0.0);
+    for (var i = 0u; i < lights.count; i += 1u) {
+        var visibility = 1.0;
+        if (i32(i) == shadow.light_index) {
+            visibility = shadow_visibility(in.world_position, normalize(in.world_normal));
+        }
+        lighting += blinn_phong(lights.lights[i], normal, view_dir, in.world_position, visibility);
+    }
+
+    return vec4<f32>(lighting * object_color.rgb, object_color.a);
+}
+
+// How much of the shadow casting light reaches `world_position`: 0 is fully
+// shadowed, 1 fully lit.
+fn shadow_visibility(world_position: vec3<f32>, geometry_normal: vec3<f32>) -> f32 {
+    // Pick the first cascade that reaches past the fragment's view depth.
+    let view_depth = dot(world_position - shadow.camera_position, shadow.camera_forward);
+    var cascade = shadow.cascade_count;
+    for (var i = 0u; i < shadow.cascade_count; i += 1u) {
+        if (view_depth < shadow.splits[i]) {
+            cascade = i;
+            break;
+        }
+    }
+    if (cascade >= shadow.cascade_count) {
+        return 1.0;
+    }
+
+    // Offset along the normal by a number of shadow map texels; the cascade
+    // matrix scales x by 1 / half its width in world units.
+    let light_view_proj = shadow.cascades[cascade];
+    let half_width = 1.0 / length(vec3<f32>(light_view_proj[0].x, light_view_proj[1].x, light_view_proj[2].x));
+    let world_texel = 2.0 * half_width * shadow.texel_size;
+    let biased_position = world_position + geometry_normal * world_texel * shadow.normal_bias;
+
+    let light_clip = light_view_proj * vec4<f32>(biased_position, 1.0);
+    let ndc = light_clip.xyz / light_clip.w;
+    if (ndc.z > 1.0) {
+        return 1.0;
+    }
+    // NDC y points up, texture v points down
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, ndc.y * -0.5 + 0.5);
+    let reference = ndc.z - shadow.depth_bias;
+
+    // Percentage closer filtering over a (2r + 1)^2 texel kernel
+    var lit = 0.0;
+    var samples = 0.0;
+    for (var x = -shadow.pcf_radius; x <= shadow.pcf_radius; x += 1) {
+        for (var y = -shadow.pcf_radius; y <= shadow.pcf_radius; y += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadow.texel_size;
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, i32(cascade), reference);
+            samples += 1.0;
+        }
+    }
+    return lit / samples;
+}
+
+// Blinn-Phong: ambient + diffuse + specular using the half vector, scaled by
+// the light's falloff. Shadows only take away the direct part.
+fn blinn_phong(light: Light, normal: vec3<f32>, view_dir: vec3<f32>, world_position: vec3<f32>, visibility: f32) -> vec3<f32> {
+    var light_dir: vec3<f32>;
+    var strength = light.intensity;
+    if (light.kind == LIGHT_DIRECTIONAL) {
+        light_dir = -light.direction;
+    } else {
+        let to_light = light.position - world_position;
+        let distance = length(to_light);
+        light_dir = to_light / distance;
+
+        let attenuation = light.attenuation.x
+            + light.attenuation.y * distance
+            + light.attenuation.z * distance * distance;
+        strength /= max(attenuation, 0.0001);
+
+        if (light.range > 0.0) {
+            // Smooth window so the light reaches exactly zero at its range.
+            let ratio = distance / light.range;
+            let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+            strength *= window * window;
+        }
+
+        if (light.kind == LIGHT_SPOT) {
+            let cos_angle = dot(-light_dir, light.direction);
+            strength *= smoothstep(light.cos_outer, light.cos_inner, cos_angle);
+        }
+    }
+
+    let half_dir = normalize(view_dir + light_dir);
+
+    let ambient_strength = 0.1;
+    let diffuse_strength = max(dot(normal, light_dir), 0.0);
+    let specular_strength = pow(max(dot(normal, half_dir), 0.0), 32.0);
+
+    return light.color * strength * (ambient_strength + visibility * (diffuse_strength + specular_strength));
+}
@@ -0,0 +1,409 @@
+use cgmath::prelude::*;
+
+use crate::asset_cache::Handle;
+use crate::instances::DrawRun;
+use crate::model::{self, DrawShadow, Vertex};
+use crate::{texture, Camera, InstanceRaw, OPENGL_TO_WGPU_MATRIX};
+
+/// Most cascades the shader knows about.
+pub const MAX_CASCADES: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowSettings {
+    /// Width and height of each cascade's shadow map in texels.
+    pub resolution: u32,
+    /// Number of cascades the view frustum is split into, 1 to `MAX_CASCADES`.
+    pub cascade_count: u32,
+    /// Only geometry closer to the camera than this receives shadows.
+    pub max_distance: f32,
+    /// Blend between uniform (0) and logarithmic (1) cascade splits.
+    pub split_lambda: f32,
+    /// Subtracted from the fragment's depth in light space before comparing.
+    pub depth_bias: f32,
+    /// Moves the lookup position along the surface normal, in shadow map
+    /// texels, to avoid acne on surfaces at grazing angles.
+    pub normal_bias: f32,
+    /// PCF kernel radius in texels; 0 uses just the hardware 2x2 filter.
+    pub pcf_radius: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            cascade_count: 3,
+            max_distance: 60.0,
+            split_lambda: 0.5,
+            depth_bias: 0.0005,
+            normal_bias: 1.5,
+            pcf_radius: 1,
+        }
+    }
+}
+
+// Matches `Shadow` in shader.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    cascades: [[[f32; 4]; 4]; MAX_CASCADES],
+    // View depth at which each cascade ends
+    splits: [f32; 4],
+    camera_forward: [f32; 3],
+    cascade_count: u32,
+    camera_position: [f32; 3],
+    // Index of the shadow casting light in the light list, or -1 for none
+    light_index: i32,
+    depth_bias: f32,
+    normal_bias: f32,
+    pcf_radius: i32,
+    texel_size: f32,
+}
+
+/// Cascaded shadow maps for a single directional light.
+pub struct ShadowMap {
+    settings: ShadowSettings,
+    map: texture::Texture,
+    cascade_views: Vec<wgpu::TextureView>,
+    uniform: ShadowUniform,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    // One light space matrix per cascade, each at its own dynamic offset
+    cascade_buffer: wgpu::Buffer,
+    cascade_stride: wgpu::BufferAddress,
+    pass_bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowMap {
+    pub fn new(device: &wgpu::Device, settings: ShadowSettings) -> Self {
+        let settings = Self::sanitize(settings);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("shadow_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Uniform Buffer"),
+            size: std::mem::size_of::<ShadowUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let cascade_stride = (std::mem::size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress)
+            .max(device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress);
+        let cascade_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Cascade Buffer"),
+            size: cascade_stride * MAX_CASCADES as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let pass_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("shadow_pass_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<[[f32; 4]; 4]>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pass_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow_pass_bind_group"),
+            layout: &pass_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &cascade_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<[[f32; 4]; 4]>() as u64),
+                    }),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&pass_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shadow.wgsl").into()),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            // Depth only
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                // Slope scaled bias handles the steep surfaces the constant
+                // bias in the shader can't.
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        let (map, cascade_views, bind_group) = Self::create_map(device, &settings, &bind_group_layout, &uniform_buffer);
+
+        Self {
+            settings,
+            map,
+            cascade_views,
+            uniform: bytemuck::Zeroable::zeroed(),
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+            cascade_buffer,
+            cascade_stride,
+            pass_bind_group,
+            pipeline,
+        }
+    }
+
+    fn sanitize(mut settings: ShadowSettings) -> ShadowSettings {
+        settings.cascade_count = settings.cascade_count.clamp(1, MAX_CASCADES as u32);
+        settings.resolution = settings.resolution.max(1);
+        settings
+    }
+
+    fn create_map(
+        device: &wgpu::Device,
+        settings: &ShadowSettings,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> (texture::Texture, Vec<wgpu::TextureView>, wgpu::BindGroup) {
+        let map = texture::Texture::create_shadow_map(device, settings.resolution, settings.cascade_count, "shadow_map");
+        let cascade_views = (0..settings.cascade_count)
+            .map(|layer| {
+                map.texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("shadow_cascade_view"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&map.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&map.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        (map, cascade_views, bind_group)
+    }
+
+    pub fn settings(&self) -> &ShadowSettings {
+        &self.settings
+    }
+
+    /// Changes the settings, recreating the shadow map if its resolution or
+    /// cascade count changed.
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: ShadowSettings) {
+        let settings = Self::sanitize(settings);
+        if settings.resolution != self.settings.resolution || settings.cascade_count != self.settings.cascade_count {
+            (self.map, self.cascade_views, self.bind_group) =
+                Self::create_map(device, &settings, &self.bind_group_layout, &self.uniform_buffer);
+        }
+        self.settings = settings;
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    /// Bind group for sampling the shadow map in the main pass.
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Fits the cascades around the camera's view frustum and uploads them.
+    /// `light` is the index and direction of the shadow casting light, if any.
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera, light: Option<(usize, cgmath::Vector3<f32>)>) {
+        let settings = self.settings;
+        let count = settings.cascade_count as usize;
+        let near = camera.projection.znear();
+        let far = camera.projection.zfar().min(settings.max_distance).max(near);
+
+        let mut splits = [0.0; 4];
+        for (i, split) in splits.iter_mut().enumerate().take(count) {
+            let p = (i + 1) as f32 / count as f32;
+            let log = near * (far / near).powf(p);
+            let uniform = near + (far - near) * p;
+            *split = settings.split_lambda * log + (1.0 - settings.split_lambda) * uniform;
+        }
+
+        let forward = (camera.target - camera.eye).normalize();
+        let mut uniform = ShadowUniform {
+            cascades: [cgmath::Matrix4::identity().into(); MAX_CASCADES],
+            splits,
+            camera_forward: forward.into(),
+            cascade_count: settings.cascade_count,
+            camera_position: camera.eye.into(),
+            light_index: -1,
+            depth_bias: settings.depth_bias,
+            normal_bias: settings.normal_bias,
+            pcf_radius: settings.pcf_radius as i32,
+            texel_size: 1.0 / settings.resolution as f32,
+        };
+
+        if let Some((index, direction)) = light {
+            uniform.light_index = index as i32;
+            let direction = direction.normalize();
+            let mut cascade_near = near;
+            for (i, &split) in splits.iter().enumerate().take(count) {
+                let matrix = self.cascade_matrix(camera, cascade_near, split, direction);
+                uniform.cascades[i] = matrix.into();
+                queue.write_buffer(
+                    &self.cascade_buffer,
+                    i as wgpu::BufferAddress * self.cascade_stride,
+                    bytemuck::cast_slice(&uniform.cascades[i]),
+                );
+                cascade_near = split;
+            }
+        }
+
+        self.uniform = uniform;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Orthographic light space matrix covering the part of the view frustum
+    /// between `near` and `far`.
+    fn cascade_matrix(&self, camera: &Camera, near: f32, far: f32, direction: cgmath::Vector3<f32>) -> cgmath::Matrix4<f32> {
+        let corners = camera.frustum_corners(near, far);
+        let center = corners.iter().fold(cgmath::Vector3::zero(), |sum, c| sum + c.to_vec()) / corners.len() as f32;
+        let center = cgmath::Point3::from_vec(center);
+        // A bounding sphere keeps the cascade's size constant while the camera
+        // turns, which stops the shadow edges from shimmering.
+        let radius = corners.iter().map(|c| c.distance(center)).fold(0.0, f32::max).ceil();
+
+        let up = if direction.y.abs() > 0.99 { cgmath::Vector3::unit_z() } else { cgmath::Vector3::unit_y() };
+        // Pull the light back so casters outside the view still cast into it.
+        let caster_margin = radius * 2.0;
+        let eye = center - direction * (radius + caster_margin);
+        let view = cgmath::Matrix4::look_at_rh(eye, center, up);
+
+        // Snap the center to whole texels for the same reason.
+        let texels_per_unit = self.settings.resolution as f32 / (radius * 2.0);
+        let snapped = view.transform_point(center);
+        let offset = cgmath::Vector3::new(
+            (snapped.x * texels_per_unit).round() / texels_per_unit - snapped.x,
+            (snapped.y * texels_per_unit).round() / texels_per_unit - snapped.y,
+            0.0,
+        );
+        let view = cgmath::Matrix4::from_translation(offset) * view;
+
+        let proj = cgmath::ortho(-radius, radius, -radius, radius, 0.0, radius * 2.0 + caster_margin);
+        OPENGL_TO_WGPU_MATRIX * proj * view
+    }
+
+    /// Renders the depth of every instance into each cascade.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        models: &[Handle<model::Model>],
+        instance_buffer: &wgpu::Buffer,
+        runs: &[DrawRun],
+    ) {
+        if self.uniform.light_index < 0 {
+            return;
+        }
+        for (i, view) in self.cascade_views.iter().enumerate() {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.pass_bind_group, &[(i as wgpu::BufferAddress * self.cascade_stride) as u32]);
+            pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            for run in runs {
+                if let Some(model) = models.get(run.model) {
+                    pass.draw_shadow_model_instanced(model, run.instances.clone());
+                }
+            }
+        }
+    }
+}
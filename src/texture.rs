@@ -9,11 +9,15 @@ pub struct Texture {
 
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    /// Tangent-space normal pointing straight out of the surface.
+    pub const FLAT_NORMAL: [u8; 4] = [128, 128, 255, 255];
 
-    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+    /// With `reverse_z` the sampler's comparison treats larger depths as
+    /// closer, matching a depth buffer cleared to 0.
+    pub fn create_depth_texture(device: &wgpu::Device, width: u32, height: u32, reverse_z: bool, label: &str) -> Self {
         let size = wgpu::Extent3d {
-            width: config.width,
-            height: config.height,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
@@ -30,6 +34,49 @@ impl Texture {
         let texture = device.create_texture(&desc);
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(
+            &wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                compare: Some(if reverse_z { wgpu::CompareFunction::GreaterEqual } else { wgpu::CompareFunction::LessEqual }),
+                lod_min_clamp: 0.0,
+                lod_max_clamp: 100.0,
+                ..Default::default()
+            }
+        );
+
+        Self { texture, view, sampler }
+    }
+
+    /// Depth texture array with one layer per shadow cascade. The view covers
+    /// all layers and the sampler does hardware depth comparison.
+    pub fn create_shadow_map(device: &wgpu::Device, resolution: u32, layers: u32, label: &str) -> Self {
+        let size = wgpu::Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: layers,
+        };
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[Self::DEPTH_FORMAT],
+        };
+        let texture = device.create_texture(&desc);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
         let sampler = device.create_sampler(
             &wgpu::SamplerDescriptor {
                 address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -48,21 +95,51 @@ impl Texture {
         Self { texture, view, sampler }
     }
 
+    /// GPU memory the texture's texels take, over every mip level and layer.
+    /// Drivers may pad or align on top of this.
+    pub fn gpu_bytes(&self) -> u64 {
+        let info = self.texture.format().describe();
+        let (block_width, block_height) = info.block_dimensions;
+        let size = self.texture.size();
+        let layers = (size.depth_or_array_layers * self.texture.sample_count()) as u64;
+        (0..self.texture.mip_level_count())
+            .map(|level| {
+                let width = (size.width >> level).max(1).div_ceil(block_width as u32);
+                let height = (size.height >> level).max(1).div_ceil(block_height as u32);
+                width as u64 * height as u64 * info.block_size as u64 * layers
+            })
+            .sum()
+    }
+
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8],
-        label: &str
+        label: &str,
+        is_normal_map: bool,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_image(device, queue, &img, Some(label), is_normal_map)
+    }
+
+    /// A 1x1 texture of a single color, for materials without an image.
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+        is_normal_map: bool,
+    ) -> Result<Self> {
+        let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+        Self::from_image(device, queue, &img, Some(label), is_normal_map)
     }
 
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
-        label: Option<&str>
+        label: Option<&str>,
+        is_normal_map: bool,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -80,7 +157,12 @@ impl Texture {
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
 				// Most images are stored using sRGB so we need to reflect that here.
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+				// Normal maps hold vectors rather than colors, so they stay linear.
+                format: if is_normal_map {
+                    wgpu::TextureFormat::Rgba8Unorm
+                } else {
+                    wgpu::TextureFormat::Rgba8UnormSrgb
+                },
                 // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
         		// COPY_DST means that we want to copy data to this texture
                 usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
@@ -0,0 +1,144 @@
+use std::time::{Duration, Instant};
+
+/// How [`FrameClock::steps`] splits the time between frames into updates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timestep {
+    /// One update per frame covering the whole frame.
+    Variable,
+    /// Updates of exactly this length. Time that doesn't fill a whole step
+    /// carries over to the next frame.
+    Fixed(Duration),
+}
+
+/// Measures frame times and hands them out as update steps.
+pub struct FrameClock {
+    timestep: Timestep,
+    max_delta: Duration,
+    start: Instant,
+    last_tick: Instant,
+    delta: Duration,
+    // Frame time not yet handed out by `steps`
+    accumulator: Duration,
+    frame_count: u64,
+}
+
+impl FrameClock {
+    /// # Panics
+    ///
+    /// If `timestep` is a zero length fixed step.
+    pub fn new(timestep: Timestep) -> Self {
+        Self::check_timestep(timestep);
+        let now = Instant::now();
+        Self {
+            timestep,
+            // Long stalls (dragging the window, a breakpoint) would otherwise
+            // run a burst of fixed steps or one giant leap.
+            max_delta: Duration::from_millis(250),
+            start: now,
+            last_tick: now,
+            delta: Duration::ZERO,
+            accumulator: Duration::ZERO,
+            frame_count: 0,
+        }
+    }
+
+    fn check_timestep(timestep: Timestep) {
+        if let Timestep::Fixed(step) = timestep {
+            assert!(!step.is_zero(), "fixed timestep must be longer than zero");
+        }
+    }
+
+    pub fn timestep(&self) -> Timestep {
+        self.timestep
+    }
+
+    /// # Panics
+    ///
+    /// If `timestep` is a zero length fixed step.
+    pub fn set_timestep(&mut self, timestep: Timestep) {
+        Self::check_timestep(timestep);
+        self.timestep = timestep;
+        self.accumulator = Duration::ZERO;
+    }
+
+    /// Longest frame the clock will report; anything longer is cut down to it.
+    pub fn set_max_delta(&mut self, max_delta: Duration) {
+        self.max_delta = max_delta;
+    }
+
+    /// Starts a new frame, returning the time since the previous one.
+    pub fn tick(&mut self) -> Duration {
+        self.tick_at(Instant::now())
+    }
+
+    /// Like [`FrameClock::tick`] with an explicit time, for driving the clock
+    /// without waiting on the real one.
+    pub fn tick_at(&mut self, now: Instant) -> Duration {
+        self.delta = now.saturating_duration_since(self.last_tick).min(self.max_delta);
+        self.last_tick = now;
+        self.accumulator += self.delta;
+        self.frame_count += 1;
+        self.delta
+    }
+
+    /// Time between the last two ticks.
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    /// Time from creating the clock to the last tick.
+    pub fn elapsed(&self) -> Duration {
+        self.last_tick - self.start
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Frames per second going by the last frame alone.
+    pub fn fps(&self) -> f32 {
+        if self.delta.is_zero() {
+            0.0
+        } else {
+            1.0 / self.delta.as_secs_f32()
+        }
+    }
+
+    /// The updates to run this frame, each yielding its length.
+    pub fn steps(&mut self) -> Steps<'_> {
+        Steps { clock: self }
+    }
+
+    /// How far into the next fixed step the leftover time reaches, from 0 to
+    /// 1, for blending between the last two update states. Always 0 with a
+    /// variable timestep.
+    pub fn interpolation(&self) -> f32 {
+        match self.timestep {
+            Timestep::Variable => 0.0,
+            Timestep::Fixed(step) => self.accumulator.as_secs_f32() / step.as_secs_f32(),
+        }
+    }
+}
+
+/// Iterator returned by [`FrameClock::steps`].
+pub struct Steps<'a> {
+    clock: &'a mut FrameClock,
+}
+
+impl Iterator for Steps<'_> {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        let clock = &mut *self.clock;
+        match clock.timestep {
+            Timestep::Variable if !clock.accumulator.is_zero() => {
+                Some(std::mem::take(&mut clock.accumulator))
+            }
+            Timestep::Fixed(step) if clock.accumulator >= step => {
+                clock.accumulator -= step;
+                Some(step)
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Context};
+
+use crate::asset_paths::{self, SearchPaths};
+use crate::pack::Pack;
+
+/// Somewhere assets can come from.
+#[derive(Debug)]
+pub enum Mount {
+    /// Loose files below a directory.
+    Dir(PathBuf),
+    Pack(Pack),
+    /// The copy of `res/` compiled into the binary.
+    #[cfg(feature = "embed-assets")]
+    Embedded,
+}
+
+impl Mount {
+    /// `name` is already normalized.
+    fn read(&self, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match self {
+            Self::Dir(dir) => {
+                let path = dir.join(name);
+                if !path.is_file() {
+                    return Ok(None);
+                }
+                std::fs::read(&path)
+                    .with_context(|| format!("cannot read {}", path.display()))
+                    .map(Some)
+            }
+            Self::Pack(pack) => pack.read(name),
+            #[cfg(feature = "embed-assets")]
+            Self::Embedded => Ok(crate::embedded_assets::get(name).map(<[u8]>::to_vec)),
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        match self {
+            Self::Dir(dir) => dir.join(name).is_file(),
+            Self::Pack(pack) => pack.entry(name).is_some(),
+            #[cfg(feature = "embed-assets")]
+            Self::Embedded => crate::embedded_assets::get(name).is_some(),
+        }
+    }
+}
+
+/// Asset names as one tree, put together from directories and packs.
+///
+/// A lookup goes through the mounts from the highest priority down and takes
+/// the first one that has the asset, so a mount overrides lower ones file by
+/// file. Among mounts of the same priority the one mounted first wins, like
+/// for [`SearchPaths`].
+#[derive(Debug, Default)]
+pub struct Vfs {
+    // Sorted by priority, highest first
+    mounts: Vec<(i32, Mount)>,
+}
+
+impl Vfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The search path directories at priority 0 in their order, then with
+    /// the `embed-assets` feature the embedded assets at `i32::MIN`.
+    pub fn from_search_paths(paths: &SearchPaths) -> Self {
+        let mut vfs = Self::new();
+        for dir in paths.dirs() {
+            vfs.mount(Mount::Dir(dir.clone()), 0);
+        }
+        #[cfg(feature = "embed-assets")]
+        vfs.mount(Mount::Embedded, i32::MIN);
+        vfs
+    }
+
+    pub fn mount(&mut self, mount: Mount, priority: i32) {
+        let index = self.mounts.partition_point(|(existing, _)| *existing >= priority);
+        self.mounts.insert(index, (priority, mount));
+    }
+
+    pub fn mount_dir(&mut self, dir: impl Into<PathBuf>, priority: i32) {
+        self.mount(Mount::Dir(dir.into()), priority);
+    }
+
+    /// Opens the pack at `path` and mounts it.
+    pub fn mount_pack(&mut self, path: impl AsRef<Path>, priority: i32) -> anyhow::Result<()> {
+        self.mount(Mount::Pack(Pack::open(path)?), priority);
+        Ok(())
+    }
+
+    /// The mounts in the order lookups try them.
+    pub fn mounts(&self) -> impl Iterator<Item = (i32, &Mount)> {
+        self.mounts.iter().map(|(priority, mount)| (*priority, mount))
+    }
+
+    pub fn contains(&self, file_name: &str) -> bool {
+        normalize(file_name).is_some_and(|name| self.mounts.iter().any(|(_, mount)| mount.contains(&name)))
+    }
+
+    /// The asset at `file_name`, a path with `/` between directories, from
+    /// the mount that wins for it.
+    pub fn read(&self, file_name: &str) -> anyhow::Result<Vec<u8>> {
+        let name = normalize(file_name).with_context(|| format!("invalid asset name {}", file_name))?;
+        for (_, mount) in &self.mounts {
+            if let Some(data) = mount.read(&name)? {
+                return Ok(data);
+            }
+        }
+        Err(anyhow!("cannot find asset {} in any of {:?}", file_name, self.mounts))
+    }
+}
+
+/// `file_name` with `/` between directories and no `.` or `..`, the form
+/// packs and the embedded assets index by. `None` for names that leave the
+/// root.
+pub fn normalize(file_name: &str) -> Option<String> {
+    let file_name = file_name.replace('\\', "/");
+    let mut parts = Vec::new();
+    for component in Path::new(&file_name).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::ParentDir => {
+                parts.pop()?;
+            }
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(parts.join("/"))
+}
+
+static GLOBAL: RwLock<Option<Arc<Vfs>>> = RwLock::new(None);
+
+/// Makes every later asset load read from `vfs`.
+pub fn set_global(vfs: Vfs) {
+    *GLOBAL.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(vfs));
+}
+
+/// What assets are loaded from: the VFS last given to [`set_global`], or
+/// without one [`Vfs::from_search_paths`] for the current search paths.
+pub fn global() -> Arc<Vfs> {
+    let vfs = GLOBAL.read().unwrap_or_else(|e| e.into_inner()).clone();
+    vfs.unwrap_or_else(|| Arc::new(Vfs::from_search_paths(&asset_paths::search_paths())))
+}
@@ -0,0 +1,52 @@
+//! Sharing and freeing of cached assets, on a headless renderer.
+
+use std::path::PathBuf;
+
+use wgpu_learn_01::asset_cache::Handle;
+use wgpu_learn_01::vfs::{self, Vfs};
+use wgpu_learn_01::HeadlessState;
+
+#[test]
+fn models_and_textures_are_shared_until_unused() {
+    // A second model file using the same material as cube.obj
+    let res = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("res");
+    let extra = std::env::temp_dir().join(format!("wgpu-learn-asset-cache-{}", std::process::id()));
+    std::fs::create_dir_all(&extra).unwrap();
+    std::fs::copy(res.join("cube.obj"), extra.join("cube2.obj")).unwrap();
+    let mut vfs = Vfs::new();
+    vfs.mount_dir(&res, 0);
+    vfs.mount_dir(&extra, 1);
+    vfs::set_global(vfs);
+
+    let mut state = match pollster::block_on(HeadlessState::new(64, 64)) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("skipping asset cache test: {}", e);
+            return;
+        }
+    };
+    let renderer = state.renderer_mut();
+    let one_model = renderer.assets().gpu_memory();
+
+    let mut scene = renderer.scene();
+    scene.models = vec!["cube.obj".into(), "./cube.obj".into(), "cube2.obj".into()];
+    scene.instances.clear();
+    pollster::block_on(renderer.load_scene(&scene)).unwrap();
+    let models = renderer.models();
+    assert!(Handle::ptr_eq(&models[0], &models[1]));
+    assert!(!Handle::ptr_eq(&models[0], &models[2]));
+    assert!(Handle::ptr_eq(
+        &models[0].materials[0].diffuse_texture,
+        &models[2].materials[0].diffuse_texture
+    ));
+    // The light uses cube.obj as well
+    assert_eq!(renderer.assets().model_count(), 2);
+    let two_models = renderer.assets().gpu_memory();
+    assert_eq!(two_models.textures, one_model.textures);
+    assert_eq!(two_models.meshes, 2 * one_model.meshes);
+
+    scene.models.clear();
+    pollster::block_on(renderer.load_scene(&scene)).unwrap();
+    assert_eq!(renderer.assets().model_count(), 1);
+    assert_eq!(renderer.assets().gpu_memory(), one_model);
+}
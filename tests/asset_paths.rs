@@ -0,0 +1,58 @@
+//! Asset lookup through search paths, which needs no GPU or window.
+
+use std::path::PathBuf;
+
+use wgpu_learn_01::asset_paths::{self, SearchPaths};
+use wgpu_learn_01::resources;
+
+// A fresh directory per test, so tests running in parallel don't collide
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("wgpu-learn-assets-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn earlier_directories_win() {
+    let root = temp_dir("order");
+    let (first, second) = (root.join("first"), root.join("second"));
+    std::fs::create_dir_all(first.join("scenes")).unwrap();
+    std::fs::create_dir_all(&second).unwrap();
+    std::fs::write(first.join("scenes/a.txt"), "first").unwrap();
+    std::fs::write(second.join("b.txt"), "second").unwrap();
+    std::fs::write(second.join("scenes.txt"), "not a directory").unwrap();
+
+    let paths = SearchPaths::new([first.clone(), second.clone()]);
+    assert_eq!(paths.resolve("scenes/a.txt").unwrap(), first.join("scenes/a.txt"));
+    assert_eq!(paths.resolve("b.txt").unwrap(), second.join("b.txt"));
+
+    // Overriding a single file only takes putting it in an earlier directory
+    std::fs::write(first.join("b.txt"), "override").unwrap();
+    assert_eq!(paths.resolve("b.txt").unwrap(), first.join("b.txt"));
+
+    let error = paths.resolve("missing.txt").unwrap_err().to_string();
+    assert!(error.contains("missing.txt"), "{}", error);
+    // Directories are not assets
+    assert!(paths.resolve("scenes").is_err());
+}
+
+#[test]
+fn defaults_fall_back_to_the_build_directory() {
+    let extra = temp_dir("defaults");
+    let paths = SearchPaths::with_defaults([extra.clone()]);
+    assert_eq!(paths.dirs().first(), Some(&extra));
+    assert!(paths.resolve("cube.obj").is_ok());
+}
+
+#[test]
+fn loads_go_through_the_search_paths() {
+    let dir = temp_dir("loads");
+    std::fs::write(dir.join("cube.mtl"), "newmtl Override").unwrap();
+    asset_paths::set_search_paths(SearchPaths::with_defaults([dir]));
+
+    let text = pollster::block_on(resources::load_string("cube.mtl")).unwrap();
+    assert_eq!(text, "newmtl Override");
+    // Anything not overridden still comes from the defaults
+    assert!(pollster::block_on(resources::load_binary("cube.obj")).is_ok());
+}
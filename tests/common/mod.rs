@@ -0,0 +1,26 @@
+//! Fixtures shared by the tests that need no GPU or window.
+
+use cgmath::prelude::*;
+use wgpu_learn_01::bounds::Aabb;
+use wgpu_learn_01::{Camera, Instance, Projection};
+
+/// Bounds of res/cube.obj.
+pub fn unit_cube() -> Aabb {
+    Aabb::new((-1.0, -1.0, -1.0).into(), (1.0, 1.0, 1.0).into())
+}
+
+pub fn instance(x: f32, y: f32, z: f32) -> Instance {
+    Instance::new(cgmath::Vector3::new(x, y, z), cgmath::Quaternion::one())
+}
+
+/// Looking down -z at the origin from 10 units away.
+pub fn camera(aspect: f32, projection: Projection, reverse_z: bool) -> Camera {
+    Camera {
+        eye: (0.0, 0.0, 10.0).into(),
+        target: (0.0, 0.0, 0.0).into(),
+        up: cgmath::Vector3::unit_y(),
+        aspect,
+        projection,
+        reverse_z,
+    }
+}
@@ -0,0 +1,75 @@
+//! Frustum extraction and culling, which need no GPU or window.
+
+mod common;
+
+use common::{camera, instance, unit_cube};
+use wgpu_learn_01::culling::{stale_range, visible_instances};
+use wgpu_learn_01::Projection;
+
+#[test]
+fn frustum_contains_what_the_camera_sees() {
+    for (projection, reverse_z) in [
+        (Projection::perspective(cgmath::Deg(90.0), 1.0, 50.0), false),
+        (Projection::perspective(cgmath::Deg(90.0), 1.0, 50.0), true),
+        (Projection::orthographic(20.0, 1.0, 50.0), false),
+        (Projection::infinite_perspective(cgmath::Deg(90.0), 1.0), true),
+    ] {
+        let frustum = camera(1.0, projection, reverse_z).frustum();
+        assert!(frustum.contains_point((0.0, 0.0, 0.0).into()), "{:?}", projection);
+        assert!(frustum.contains_point((9.0, 9.0, 0.0).into()), "{:?}", projection);
+        // Behind the camera and closer than the near plane
+        assert!(!frustum.contains_point((0.0, 0.0, 11.0).into()), "{:?}", projection);
+        assert!(!frustum.contains_point((0.0, 0.0, 9.5).into()), "{:?}", projection);
+        // Off to the side
+        assert!(!frustum.contains_point((11.0, 0.0, 0.0).into()), "{:?}", projection);
+        assert!(!frustum.contains_point((0.0, -11.0, 0.0).into()), "{:?}", projection);
+    }
+
+    let far = (0.0, 0.0, -1000.0).into();
+    assert!(!camera(1.0, Projection::perspective(cgmath::Deg(90.0), 1.0, 50.0), false).frustum().contains_point(far));
+    assert!(camera(1.0, Projection::infinite_perspective(cgmath::Deg(90.0), 1.0), true).frustum().contains_point(far));
+}
+
+#[test]
+fn culls_instances_outside_the_view() {
+    let frustum = camera(1.0, Projection::perspective(cgmath::Deg(90.0), 1.0, 50.0), false).frustum();
+    let instances = vec![
+        instance(0.0, 0.0, 0.0),
+        // Centered outside but poking into the view
+        instance(10.5, 0.0, 0.0),
+        instance(30.0, 0.0, 0.0),
+        instance(0.0, 0.0, 20.0),
+        instance(0.0, 0.0, -100.0),
+    ];
+    let visible = visible_instances(&frustum, &instances, &[Some(unit_cube())]).collect::<Vec<_>>();
+    assert_eq!(visible, vec![0, 1]);
+}
+
+#[test]
+fn culling_follows_instance_scale() {
+    let frustum = camera(1.0, Projection::perspective(cgmath::Deg(90.0), 1.0, 50.0), false).frustum();
+    let mut wide = instance(15.0, 0.0, 0.0);
+    assert_eq!(visible_instances(&frustum, &[wide], &[Some(unit_cube())]).count(), 0);
+    wide.scale = cgmath::Vector3::new(10.0, 1.0, 1.0);
+    assert_eq!(visible_instances(&frustum, &[wide], &[Some(unit_cube())]).count(), 1);
+}
+
+#[test]
+fn only_stale_culled_instances_get_rewritten() {
+    // Same view, nothing edited
+    assert_eq!(stale_range(&[0, 2, 5], &[0, 2, 5], None), None);
+    assert_eq!(stale_range(&[0, 2, 5], &[0, 2, 5], Some(3..5)), None);
+    // Edits to visible instances
+    assert_eq!(stale_range(&[0, 2, 5], &[0, 2, 5], Some(2..3)), Some(1..2));
+    assert_eq!(stale_range(&[0, 2, 5], &[0, 2, 5], Some(1..9)), Some(1..3));
+
+    // Everything after an instance coming into view moves up
+    assert_eq!(stale_range(&[0, 2, 5], &[0, 1, 2, 5], None), Some(1..4));
+    assert_eq!(stale_range(&[0, 2, 5], &[0, 2, 5, 7], None), Some(3..4));
+    assert_eq!(stale_range(&[0, 2, 5], &[0, 2, 5, 7], Some(0..1)), Some(0..4));
+    // Leaving the view at the end leaves nothing to write
+    assert_eq!(stale_range(&[0, 2, 5], &[0, 2], None), None);
+    assert_eq!(stale_range(&[0, 2, 5], &[0, 5], None), Some(1..2));
+    assert_eq!(stale_range(&[0, 2, 5], &[], Some(0..9)), None);
+    assert_eq!(stale_range(&[], &[3, 4], None), Some(0..2));
+}
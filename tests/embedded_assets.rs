@@ -0,0 +1,32 @@
+//! The asset bundle compiled in with the `embed-assets` feature.
+#![cfg(feature = "embed-assets")]
+
+use wgpu_learn_01::asset_paths::{self, SearchPaths};
+use wgpu_learn_01::{embedded_assets, resources};
+
+fn on_disk(file_name: &str) -> Vec<u8> {
+    std::fs::read(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("res").join(file_name)).unwrap()
+}
+
+#[test]
+fn bundle_holds_all_of_res() {
+    let names = embedded_assets::names().collect::<Vec<_>>();
+    assert!(names.contains(&"cube.obj"));
+    assert!(names.contains(&"scenes/default.ron"));
+    assert!(names.windows(2).all(|pair| pair[0] < pair[1]));
+
+    assert_eq!(embedded_assets::get("scenes/default.ron").unwrap(), on_disk("scenes/default.ron"));
+    assert_eq!(embedded_assets::get("./scenes/../cube.obj").unwrap(), on_disk("cube.obj"));
+    assert_eq!(embedded_assets::get("scenes\\default.ron").unwrap(), on_disk("scenes/default.ron"));
+    assert!(embedded_assets::get("../cube.obj").is_none());
+    assert!(embedded_assets::get("missing.obj").is_none());
+}
+
+#[test]
+fn loads_fall_back_to_the_bundle() {
+    // No directories at all, as for a binary shipped on its own
+    asset_paths::set_search_paths(SearchPaths::new([]));
+    let data = pollster::block_on(resources::load_binary("cube-normal.png")).unwrap();
+    assert_eq!(data, on_disk("cube-normal.png"));
+    assert!(pollster::block_on(resources::load_string("missing.txt")).is_err());
+}
@@ -0,0 +1,123 @@
+//! glTF loading, on a headless renderer. The fixtures in `tests/gltf/` cover
+//! data URI, external and GLB buffers.
+
+use std::path::PathBuf;
+
+use cgmath::prelude::*;
+use wgpu_learn_01::asset_cache::Handle;
+use wgpu_learn_01::bounds::Aabb;
+use wgpu_learn_01::model::{Mesh, ModelVertex};
+use wgpu_learn_01::vfs::{self, Vfs};
+use wgpu_learn_01::{HeadlessState, Instance};
+
+fn headless() -> Option<HeadlessState> {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let mut vfs = Vfs::new();
+    vfs.mount_dir(manifest_dir.join("res"), 0);
+    vfs.mount_dir(manifest_dir.join("tests").join("gltf"), 1);
+    vfs::set_global(vfs);
+
+    match pollster::block_on(HeadlessState::new(64, 64)) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            eprintln!("skipping glTF test: {}", e);
+            None
+        }
+    }
+}
+
+fn vertex_count(mesh: &Mesh) -> u64 {
+    mesh.vertex_buffer.size() / std::mem::size_of::<ModelVertex>() as u64
+}
+
+fn aabb(min: [f32; 3], max: [f32; 3]) -> Aabb {
+    Aabb::new(min.into(), max.into())
+}
+
+#[test]
+fn gltf_fixtures_load() {
+    let Some(mut state) = headless() else {
+        return;
+    };
+    let renderer = state.renderer_mut();
+    let mut scene = renderer.scene();
+    scene.models = vec!["primitives.gltf".into(), "external.gltf".into(), "mirrored.glb".into()];
+    scene.instances.clear();
+    pollster::block_on(renderer.load_scene(&scene)).unwrap();
+    let [primitives, external, mirrored] = renderer.models() else {
+        panic!("expected three models");
+    };
+
+    // Both primitives of the one mesh, with the node transforms baked in
+    let [quad, triangle] = primitives.meshes.as_slice() else {
+        panic!("expected two meshes, got {}", primitives.meshes.len());
+    };
+    assert_eq!((vertex_count(quad), quad.num_elements), (4, 6));
+    assert_eq!(quad.bounds, aabb([-1.0, -2.0, -5.0], [3.0, 2.0, -5.0]));
+    assert_eq!((vertex_count(triangle), triangle.num_elements), (3, 3));
+    assert_eq!(triangle.bounds, aabb([1.0, 0.0, -5.0], [3.0, 2.0, -5.0]));
+
+    // The primitive without a material gets a default one after the file's
+    assert_eq!(primitives.materials.len(), 2);
+    assert_eq!((quad.material, triangle.material), (0, 1));
+    assert_eq!(primitives.materials[0].name, "Tinted");
+    assert_eq!(primitives.materials[0].base_color_factor, [1.0, 0.5, 0.25, 1.0]);
+    assert_eq!(primitives.materials[1].base_color_factor, [1.0; 4]);
+    let tinted = &primitives.materials[0];
+    assert_eq!((tinted.metallic_factor, tinted.roughness_factor), (0.25, 0.75));
+    // glTF's defaults, with a white stand-in for the missing map
+    let default = &primitives.materials[1];
+    assert_eq!((default.metallic_factor, default.roughness_factor), (1.0, 1.0));
+    assert_eq!(default.metallic_roughness_texture.texture.width(), 1);
+    assert!(Handle::ptr_eq(&tinted.metallic_roughness_texture, &default.metallic_roughness_texture));
+
+    let [triangle] = external.meshes.as_slice() else {
+        panic!("expected one mesh, got {}", external.meshes.len());
+    };
+    assert_eq!((vertex_count(triangle), triangle.num_elements), (3, 3));
+    assert_eq!(triangle.bounds, aabb([0.0, 3.0, 0.0], [1.0, 4.0, 0.0]));
+    let metal = &external.materials[0];
+    assert_eq!((metal.metallic_factor, metal.roughness_factor), (0.5, 1.0));
+    let texture = &metal.metallic_roughness_texture.texture;
+    assert_eq!((texture.width(), texture.format()), (2, wgpu::TextureFormat::Rgba8Unorm));
+
+    let [quad] = mirrored.meshes.as_slice() else {
+        panic!("expected one mesh, got {}", mirrored.meshes.len());
+    };
+    assert_eq!((vertex_count(quad), quad.num_elements), (4, 6));
+    assert_eq!(quad.bounds, aabb([-1.0, -1.0, 0.0], [1.0, 1.0, 0.0]));
+    assert_eq!(mirrored.materials[0].name, "Textured");
+    assert_eq!(mirrored.materials[0].diffuse_texture.texture.width(), 1);
+}
+
+#[test]
+fn mirrored_nodes_keep_their_front_faces() {
+    let Some(mut state) = headless() else {
+        return;
+    };
+    let renderer = state.renderer_mut();
+    let mut scene = renderer.scene();
+    scene.models = vec!["mirrored.glb".into()];
+    // The quad faces +z; the second instance is turned to show its back
+    let facing = Instance::new((-2.0, 0.0, 0.0).into(), cgmath::Quaternion::one());
+    let turned = Instance::new((2.0, 0.0, 0.0).into(), cgmath::Quaternion::from_angle_y(cgmath::Deg(180.0)));
+    scene.instances = vec![facing, turned];
+    pollster::block_on(renderer.load_scene(&scene)).unwrap();
+    renderer.camera.eye = (0.0, 0.0, 8.0).into();
+    renderer.camera.target = (0.0, 0.0, 0.0).into();
+    state.render().unwrap();
+
+    let renderer = state.renderer_mut();
+    let (width, height) = renderer.size();
+    let view_proj = renderer.camera.build_view_projection_matrix();
+    let mut pixel_of = |position: cgmath::Vector3<f32>| {
+        let ndc = view_proj.transform_point(cgmath::Point3::from_vec(position));
+        let x = (ndc.x * 0.5 + 0.5) * width as f32;
+        let y = (0.5 - ndc.y * 0.5) * height as f32;
+        renderer.pick_pixel(x as u32, y as u32).unwrap().map(|hit| hit.instance)
+    };
+    // Front faces survive back face culling only if mirroring flipped the
+    // winding back
+    assert_eq!(pixel_of(facing.position), Some(0));
+    assert_eq!(pixel_of(turned.position), None);
+}
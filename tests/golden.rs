@@ -0,0 +1,189 @@
+//! Golden-image regression tests.
+//!
+//! Every scene in [`SCENES`] is rendered offscreen and compared against
+//! `tests/golden/<name>.png`. On a mismatch the actual frame and a diff image
+//! are written to `target/tmp/golden/` so the change can be inspected.
+//!
+//! Environment variables:
+//! - `GOLDEN_BLESS=1` overwrites the references with the current output.
+//! - `GOLDEN_TOLERANCE=<n>` overrides the per-channel tolerance of every scene.
+//! - `GOLDEN_SKIP_NO_ADAPTER=1` passes without checking anything when there
+//!   is no graphics adapter, which otherwise fails the test.
+
+use std::path::{Path, PathBuf};
+
+use wgpu_learn_01::{HeadlessState, Renderer};
+
+/// How far a frame may drift from its reference before the test fails.
+#[derive(Clone, Copy, Debug)]
+struct Tolerance {
+    /// Largest allowed difference of any color channel of a pixel.
+    per_channel: u8,
+    /// Fraction of pixels that may exceed `per_channel`, to absorb
+    /// rasterization differences between drivers along edges.
+    max_failing_ratio: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            per_channel: 8,
+            max_failing_ratio: 0.002,
+        }
+    }
+}
+
+struct Scene {
+    name: &'static str,
+    width: u32,
+    height: u32,
+    tolerance: Tolerance,
+    setup: fn(&mut Renderer),
+}
+
+const SCENES: &[Scene] = &[
+    Scene {
+        name: "default_camera",
+        width: 256,
+        height: 192,
+        tolerance: Tolerance { per_channel: 8, max_failing_ratio: 0.002 },
+        setup: |renderer| {
+            renderer.camera.eye = (0.0, 1.0, 2.0).into();
+            renderer.camera.target = (0.0, 0.0, 0.0).into();
+        },
+    },
+    Scene {
+        name: "cube_grid_overview",
+        width: 320,
+        height: 240,
+        tolerance: Tolerance { per_channel: 8, max_failing_ratio: 0.005 },
+        setup: |renderer| {
+            renderer.camera.eye = (0.0, 20.0, 25.0).into();
+            renderer.camera.target = (0.0, 0.0, 0.0).into();
+        },
+    },
+    Scene {
+        name: "corner_cube",
+        width: 200,
+        height: 200,
+        tolerance: Tolerance { per_channel: 8, max_failing_ratio: 0.002 },
+        setup: |renderer| {
+            renderer.camera.eye = (15.0, 2.5, 15.0).into();
+            renderer.camera.target = (12.0, 0.0, 12.0).into();
+        },
+    },
+];
+
+struct Comparison {
+    failing_pixels: usize,
+    max_difference: u8,
+    diff: image::RgbaImage,
+}
+
+/// Compares two equally sized images. The diff image shows the unchanged
+/// frame dimmed with failing pixels in red.
+fn compare(expected: &image::RgbaImage, actual: &image::RgbaImage, tolerance: Tolerance) -> Comparison {
+    let mut failing_pixels = 0;
+    let mut max_difference = 0;
+    let diff = image::RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let e = expected.get_pixel(x, y);
+        let a = actual.get_pixel(x, y);
+        let difference = e.0.iter().zip(a.0.iter()).map(|(e, a)| e.abs_diff(*a)).max().unwrap_or(0);
+        max_difference = max_difference.max(difference);
+        if difference > tolerance.per_channel {
+            failing_pixels += 1;
+            image::Rgba([255, 0, 0, 255])
+        } else {
+            image::Rgba([a[0] / 4, a[1] / 4, a[2] / 4, 255])
+        }
+    });
+
+    Comparison {
+        failing_pixels,
+        max_difference,
+        diff,
+    }
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(format!("{}.png", name))
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden")
+}
+
+fn check_scene(state: &mut HeadlessState, scene: &Scene, bless: bool) -> Result<(), String> {
+    state.resize(scene.width, scene.height);
+    (scene.setup)(state.renderer_mut());
+    let actual = state.render().map_err(|e| format!("{}: render failed: {}", scene.name, e))?;
+
+    let reference = reference_path(scene.name);
+    if bless {
+        actual.save(&reference).map_err(|e| format!("{}: {}", scene.name, e))?;
+        println!("blessed {}", reference.display());
+        return Ok(());
+    }
+
+    let expected = image::open(&reference)
+        .map_err(|e| format!("{}: cannot open {} ({}), run with GOLDEN_BLESS=1 to create it", scene.name, reference.display(), e))?
+        .to_rgba8();
+    if expected.dimensions() != actual.dimensions() {
+        return Err(format!(
+            "{}: reference is {:?} but the frame is {:?}",
+            scene.name,
+            expected.dimensions(),
+            actual.dimensions()
+        ));
+    }
+
+    let mut tolerance = scene.tolerance;
+    if let Some(per_channel) = std::env::var("GOLDEN_TOLERANCE").ok().and_then(|t| t.parse().ok()) {
+        tolerance.per_channel = per_channel;
+    }
+
+    let comparison = compare(&expected, &actual, tolerance);
+    let allowed = (tolerance.max_failing_ratio * (scene.width * scene.height) as f32) as usize;
+    if comparison.failing_pixels <= allowed {
+        return Ok(());
+    }
+
+    let dir = output_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let actual_path = dir.join(format!("{}.actual.png", scene.name));
+    let diff_path = dir.join(format!("{}.diff.png", scene.name));
+    actual.save(&actual_path).map_err(|e| e.to_string())?;
+    comparison.diff.save(&diff_path).map_err(|e| e.to_string())?;
+
+    Err(format!(
+        "{}: {} pixels differ by more than {} (allowed {}, max difference {}), see {} and {}",
+        scene.name,
+        comparison.failing_pixels,
+        tolerance.per_channel,
+        allowed,
+        comparison.max_difference,
+        actual_path.display(),
+        diff_path.display(),
+    ))
+}
+
+#[test]
+fn golden_scenes() {
+    let mut state = match pollster::block_on(HeadlessState::new(SCENES[0].width, SCENES[0].height)) {
+        Ok(state) => state,
+        // Passing silently would hide that nothing was compared
+        Err(e) if std::env::var("GOLDEN_SKIP_NO_ADAPTER").is_ok_and(|v| v == "1") => {
+            eprintln!("skipping golden image tests: {}", e);
+            return;
+        }
+        Err(e) => panic!("golden image tests need a graphics adapter ({}), set GOLDEN_SKIP_NO_ADAPTER=1 to skip them", e),
+    };
+
+    let bless = std::env::var("GOLDEN_BLESS").is_ok_and(|v| v == "1");
+    let failures = SCENES
+        .iter()
+        .filter_map(|scene| check_scene(&mut state, scene, bless).err())
+        .collect::<Vec<_>>();
+
+    assert!(failures.is_empty(), "golden image mismatches:\n{}", failures.join("\n"));
+}
@@ -0,0 +1,88 @@
+//! Culling in a compute shader, on a headless renderer.
+
+use wgpu_learn_01::culling::Culling;
+use wgpu_learn_01::{HeadlessState, Instance, Renderer};
+
+#[test]
+fn rendering_again_without_update_culls_the_same() {
+    let mut state = match pollster::block_on(HeadlessState::new(64, 64)) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("skipping GPU culling test: {}", e);
+            return;
+        }
+    };
+    state.renderer_mut().culling = Culling::Gpu;
+    state.render().unwrap();
+    let first = state.renderer().cull_stats().unwrap();
+    assert_eq!(first.total, state.renderer().instances.len());
+    assert!(first.visible > 0 && first.visible < first.total, "{:?}", first);
+
+    // Screenshots render without updating first
+    state.renderer().capture().unwrap();
+    assert_eq!(state.renderer().cull_stats().unwrap(), first);
+    state.render().unwrap();
+    assert_eq!(state.renderer().cull_stats().unwrap(), first);
+}
+
+#[test]
+fn several_models_and_overrides_cull_like_the_cpu() {
+    let mut state = match pollster::block_on(HeadlessState::new(64, 64)) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("skipping GPU culling test: {}", e);
+            return;
+        }
+    };
+    let renderer = state.renderer_mut();
+    let mut scene = renderer.scene();
+    // The same file twice still makes two models, each with its own runs
+    scene.models = vec!["cube.obj".into(), "cube.obj".into()];
+    for (index, instance) in scene.instances.iter_mut().enumerate() {
+        instance.model = index / 7 % 2;
+        instance.material = (index % 5 == 0).then_some(0);
+        instance.color = [1.0, (index % 3) as f32 / 2.0, 0.5, 1.0];
+    }
+    // Past the end, so never drawn
+    scene.instances.push(Instance { model: 5, ..Instance::default() });
+    pollster::block_on(renderer.load_scene(&scene)).unwrap();
+
+    let cull = |state: &mut HeadlessState, culling| {
+        state.renderer_mut().culling = culling;
+        let image = state.render().unwrap();
+        (state.renderer().cull_stats().unwrap(), image)
+    };
+    let (cpu_stats, cpu_image) = cull(&mut state, Culling::Cpu);
+    let (gpu_stats, gpu_image) = cull(&mut state, Culling::Gpu);
+    assert!(cpu_stats.visible > 0 && cpu_stats.culled() > 1, "{:?}", cpu_stats);
+    assert_eq!(gpu_stats, cpu_stats);
+    assert!(gpu_image == cpu_image, "GPU culling drew something else");
+}
+
+#[test]
+fn devices_without_compute_shaders_cull_on_the_cpu() {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let Some(adapter) = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default())) else {
+        eprintln!("skipping GPU culling test: no adapter");
+        return;
+    };
+    // Storage buffers but no compute, as on some downlevel adapters
+    let limits = wgpu::Limits {
+        max_compute_workgroup_size_x: 0,
+        max_compute_workgroup_size_y: 0,
+        max_compute_workgroup_size_z: 0,
+        max_compute_invocations_per_workgroup: 0,
+        max_compute_workgroups_per_dimension: 0,
+        ..adapter.limits()
+    };
+    let descriptor = wgpu::DeviceDescriptor { features: wgpu::Features::empty(), limits, label: None };
+    let (device, queue) = pollster::block_on(adapter.request_device(&descriptor, None)).unwrap();
+    let mut renderer =
+        pollster::block_on(Renderer::new(device, queue, HeadlessState::COLOR_FORMAT, 64, 64)).unwrap();
+    assert!(!renderer.gpu_culling_supported());
+
+    renderer.culling = Culling::Gpu;
+    renderer.update();
+    assert_eq!(renderer.culling, Culling::Cpu);
+    renderer.capture().unwrap();
+}
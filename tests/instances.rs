@@ -0,0 +1,149 @@
+//! Instance list bookkeeping. Only the last test needs a GPU.
+
+use wgpu_learn_01::instances::{draw_runs, grown_capacity, DirtyRange, DrawRun};
+use wgpu_learn_01::scene_graph::{ModelAttachment, Transform};
+use wgpu_learn_01::{HeadlessState, Instance};
+
+fn instance(model: usize, material: Option<usize>) -> Instance {
+    Instance {
+        model,
+        material,
+        ..Instance::default()
+    }
+}
+
+#[test]
+fn dirty_range_spans_every_mark() {
+    let mut dirty = DirtyRange::default();
+    assert_eq!(dirty.take(10), None);
+
+    dirty.mark(4..5);
+    dirty.mark(2..2);
+    assert_eq!(dirty.range(), Some(4..5));
+    dirty.mark(7..8);
+    dirty.mark(1..2);
+    assert_eq!(dirty.range(), Some(1..8));
+    assert_eq!(dirty.take(10), Some(1..8));
+    assert_eq!(dirty.range(), None);
+
+    // Cut off where the instances end now
+    dirty.mark(3..9);
+    assert_eq!(dirty.take(5), Some(3..5));
+    dirty.mark(6..9);
+    assert_eq!(dirty.take(5), None);
+    assert_eq!(dirty.range(), None);
+}
+
+#[test]
+fn capacity_grows_to_powers_of_two() {
+    assert_eq!(grown_capacity(4, 3), 4);
+    assert_eq!(grown_capacity(4, 4), 4);
+    assert_eq!(grown_capacity(4, 5), 8);
+    assert_eq!(grown_capacity(1, 100), 128);
+    // Never shrinks
+    assert_eq!(grown_capacity(64, 0), 64);
+}
+
+#[test]
+fn draw_runs_split_on_model_and_material() {
+    let instances = [
+        instance(0, None),
+        instance(0, None),
+        instance(1, None),
+        instance(1, Some(2)),
+        instance(1, Some(2)),
+        instance(0, None),
+    ];
+    let run = |model, material, instances| DrawRun { model, material, instances };
+    assert_eq!(
+        draw_runs(&instances),
+        [run(0, None, 0..2), run(1, None, 2..3), run(1, Some(2), 3..5), run(0, None, 5..6)]
+    );
+    assert_eq!(draw_runs(&[]), []);
+}
+
+#[test]
+fn instance_list_tracks_edits_until_uploaded() {
+    let mut state = match pollster::block_on(HeadlessState::new(64, 64)) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("skipping instance list test: {}", e);
+            return;
+        }
+    };
+    let renderer = state.renderer_mut();
+    let list = &mut renderer.instances;
+    list.clear();
+    for model in [0, 1, 2] {
+        list.push(instance(model, None));
+    }
+    list.upload(&renderer.device, &renderer.queue);
+    assert_eq!(list.dirty(), None);
+    let capacity = list.capacity();
+    assert!(capacity >= 3);
+
+    list.get_mut(1).unwrap().color = [0.5; 4];
+    assert_eq!(list.dirty(), Some(1..2));
+    assert!(list.get_mut(3).is_none());
+    assert_eq!(list.dirty(), Some(1..2));
+    list.upload(&renderer.device, &renderer.queue);
+    assert_eq!(list.dirty(), None);
+
+    list.iter_mut().next();
+    assert_eq!(list.dirty(), Some(0..3));
+    list.upload(&renderer.device, &renderer.queue);
+
+    // Removing shifts the instances after it down, which all need writing
+    assert_eq!(list.remove(0).model, 0);
+    assert_eq!(list.iter().map(|instance| instance.model).collect::<Vec<_>>(), [1, 2]);
+    assert_eq!(list.dirty(), Some(0..2));
+    list.upload(&renderer.device, &renderer.queue);
+    let out_of_bounds = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| list.remove(2)));
+    assert!(out_of_bounds.is_err());
+
+    // Outgrowing the buffer reallocates it at the next power of two
+    while list.len() <= capacity {
+        list.push(instance(0, None));
+    }
+    list.upload(&renderer.device, &renderer.queue);
+    assert_eq!(list.capacity(), (capacity + 1).next_power_of_two());
+    assert_eq!(list.dirty(), None);
+}
+
+#[test]
+fn removing_instances_keeps_selection_and_scene_graph_in_place() {
+    let mut state = match pollster::block_on(HeadlessState::new(64, 64)) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("skipping instance removal test: {}", e);
+            return;
+        }
+    };
+    let renderer = state.renderer_mut();
+    renderer.instances.clear();
+    for x in 0..4 {
+        renderer.instances.push(Instance {
+            position: (x as f32, 0.0, 0.0).into(),
+            ..Instance::default()
+        });
+    }
+    let node = renderer.scene_graph.add(None, Transform::from_translation((0.0, 5.0, 0.0).into()));
+    renderer.scene_graph.attach(node, ModelAttachment::new(0));
+    renderer.update();
+    assert_eq!(renderer.scene_graph.instance(node), Some(4));
+
+    renderer.selected_instance = Some(2);
+    assert_eq!(renderer.remove_instance(0).unwrap().position.x, 0.0);
+    assert_eq!(renderer.selected_instance, Some(1));
+    assert_eq!(renderer.scene_graph.instance(node), Some(3));
+    renderer.remove_instance(1).unwrap();
+    assert_eq!(renderer.selected_instance, None);
+
+    // The graph's instance stays its own, and keeps getting the graph's edits
+    assert!(renderer.remove_instance(2).is_err());
+    assert!(renderer.remove_instance(3).is_err());
+    renderer.scene_graph.local_mut(node).unwrap().translation.y = 6.0;
+    renderer.update();
+    assert_eq!(renderer.instances.get(2).unwrap().position.y, 6.0);
+    assert_eq!(renderer.instances.get(1).unwrap().position.y, 0.0);
+}
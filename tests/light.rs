@@ -0,0 +1,38 @@
+//! Light parameters. Only the last test needs a GPU.
+
+use wgpu_learn_01::light::Light;
+use wgpu_learn_01::HeadlessState;
+
+#[test]
+fn directions_are_normalized() {
+    let light = Light::directional((0.0, -3.0, 4.0).into(), [1.0; 3]);
+    assert_eq!(light.unit_direction(), (0.0, -0.6, 0.8).into());
+}
+
+#[test]
+fn unusable_directions_point_down() {
+    let down = -cgmath::Vector3::unit_y();
+    for direction in [(0.0, 0.0, 0.0), (1.0e-30, 0.0, 0.0), (f32::NAN, 1.0, 0.0), (f32::INFINITY, 0.0, 0.0)] {
+        let light = Light::directional(direction.into(), [1.0; 3]);
+        assert_eq!(light.unit_direction(), down, "{:?}", direction);
+    }
+}
+
+#[test]
+fn zero_directions_render_like_straight_down() {
+    let mut state = match pollster::block_on(HeadlessState::new(64, 64)) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("skipping light test: {}", e);
+            return;
+        }
+    };
+    let mut render_with = |direction: cgmath::Vector3<f32>| {
+        let lights = &mut state.renderer_mut().lights;
+        lights.clear();
+        lights.add(Light::directional(direction, [1.0; 3]));
+        state.render().unwrap()
+    };
+    let down = render_with(-cgmath::Vector3::unit_y());
+    assert!(render_with(cgmath::Vector3::new(0.0, 0.0, 0.0)) == down);
+}
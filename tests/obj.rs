@@ -0,0 +1,119 @@
+//! Malformed and incomplete models, with the fixtures in `tests/obj/`, and
+//! normal generation for meshes without normals.
+
+use std::path::PathBuf;
+
+use wgpu_learn_01::model::{ModelVertex, Vertex};
+use wgpu_learn_01::resources::{generate_normals, LoadError, NormalGeneration};
+use wgpu_learn_01::vfs::{self, Vfs};
+use wgpu_learn_01::HeadlessState;
+
+fn vertex(position: [f32; 3]) -> ModelVertex {
+    ModelVertex {
+        position,
+        tex_coords: [0.0; 2],
+        normal: [0.0; 3],
+        tangent: [0.0; 3],
+        bitangent: [0.0; 3],
+    }
+}
+
+// Two triangles sharing the edge from the origin up y, one facing +z and one
+// facing +x
+fn hinge() -> (Vec<ModelVertex>, Vec<u32>) {
+    let vertices = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]]
+        .map(vertex)
+        .to_vec();
+    (vertices, vec![0, 1, 2, 0, 3, 2])
+}
+
+fn assert_normals(vertices: &[ModelVertex], expected: &[[f32; 3]]) {
+    let normals = vertices.iter().map(|vertex| vertex.normal).collect::<Vec<_>>();
+    assert_eq!(normals.len(), expected.len());
+    for (normal, expected) in normals.iter().zip(expected) {
+        let close = normal.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-6);
+        assert!(close, "{:?} != {:?}", normals, expected);
+    }
+}
+
+#[test]
+fn smooth_normals_average_shared_vertices() {
+    let (mut vertices, mut indices) = hinge();
+    generate_normals(&mut vertices, &mut indices, NormalGeneration::Smooth);
+    assert_eq!(indices, [0, 1, 2, 0, 3, 2]);
+    let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+    assert_normals(
+        &vertices,
+        &[[diagonal, 0.0, diagonal], [0.0, 0.0, 1.0], [diagonal, 0.0, diagonal], [1.0, 0.0, 0.0]],
+    );
+}
+
+#[test]
+fn flat_normals_split_vertices_per_face() {
+    let (mut vertices, mut indices) = hinge();
+    generate_normals(&mut vertices, &mut indices, NormalGeneration::Flat);
+    assert_eq!(indices, [0, 1, 2, 3, 4, 5]);
+    assert_eq!(vertices[3].position, [0.0, 0.0, 0.0]);
+    assert_normals(&vertices, &[[0.0, 0.0, 1.0]; 3].into_iter().chain([[1.0, 0.0, 0.0]; 3]).collect::<Vec<_>>());
+}
+
+#[test]
+fn malformed_models_fail_with_load_errors() {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let mut vfs = Vfs::new();
+    vfs.mount_dir(manifest_dir.join("res"), 0);
+    vfs.mount_dir(manifest_dir.join("tests").join("obj"), 1);
+    vfs.mount_dir(manifest_dir.join("tests").join("gltf"), 1);
+    vfs::set_global(vfs);
+
+    let mut state = match pollster::block_on(HeadlessState::new(64, 64)) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("skipping OBJ loading test: {}", e);
+            return;
+        }
+    };
+    let renderer = state.renderer_mut();
+    let mut scene = renderer.scene();
+    scene.instances.clear();
+    let mut load = |file_name: &str| {
+        scene.models = vec![file_name.to_string()];
+        pollster::block_on(renderer.load_scene(&scene)).map(|()| renderer.models()[0].clone())
+    };
+
+    // Missing normals, texture coordinates and materials are filled in
+    let cube = load("no_normals.obj").unwrap();
+    assert_eq!(cube.meshes.len(), 1);
+    assert_eq!(cube.meshes[0].num_elements, 36);
+    assert_eq!(cube.meshes[0].vertex_buffer.size(), 8 * ModelVertex::desc().array_stride);
+    assert_eq!(cube.materials.len(), 1);
+
+    let error = load("missing_mtl.obj").err().expect("missing_mtl.obj loaded");
+    match error.downcast_ref::<LoadError>() {
+        Some(LoadError::MissingMaterialLibrary { path }) => assert_eq!(path, "nowhere.mtl"),
+        _ => panic!("unexpected error {:?}", error),
+    }
+
+    let error = load("missing_texture.obj").err().expect("missing_texture.obj loaded");
+    match error.downcast_ref::<LoadError>() {
+        Some(LoadError::MissingTexture { material, path }) => {
+            assert_eq!((material.as_str(), path.as_str()), ("Broken", "nowhere.png"));
+        }
+        _ => panic!("unexpected error {:?}", error),
+    }
+
+    // tobj already rejects faces past the vertices
+    let error = load("bad_index.obj").err().expect("bad_index.obj loaded");
+    match error.downcast_ref::<LoadError>() {
+        Some(LoadError::Obj { file, error: tobj::LoadError::FaceVertexOutOfBounds }) => assert_eq!(file, "bad_index.obj"),
+        _ => panic!("unexpected error {:?}", error),
+    }
+
+    let error = load("bad_index.gltf").err().expect("bad_index.gltf loaded");
+    match error.downcast_ref::<LoadError>() {
+        Some(LoadError::IndexOutOfRange { mesh, index, vertex_count }) => {
+            assert_eq!((mesh.as_str(), *index, *vertex_count), ("broken", 9, 3));
+        }
+        _ => panic!("unexpected error {:?}", error),
+    }
+}
@@ -0,0 +1,115 @@
+//! Asset packs and the VFS mounting them, which need no GPU or window.
+
+use std::path::PathBuf;
+
+use wgpu_learn_01::pack::{Compression, Pack, PackBuilder};
+use wgpu_learn_01::vfs::Vfs;
+
+// A fresh directory per test, so tests running in parallel don't collide
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("wgpu-learn-pack-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Bytes deflate can't shrink, from a xorshift generator
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+fn res() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("res")
+}
+
+#[test]
+fn packs_give_back_what_went_in() {
+    let dir = temp_dir("roundtrip");
+    let path = dir.join("res.pack");
+    let mut builder = PackBuilder::new(Compression::Deflate);
+    builder.add_dir(res()).unwrap();
+    builder.add("./extra/../notes.txt", b"replaced".to_vec()).unwrap();
+    builder.add("notes.txt", b"text ".repeat(100)).unwrap();
+    builder.add("noise.bin", noise(4096)).unwrap();
+    assert!(builder.add("../outside.txt", Vec::new()).is_err());
+    let written = builder.write(&path).unwrap();
+
+    let pack = Pack::open(&path).unwrap();
+    assert_eq!(pack.entries(), written);
+    for name in ["cube.obj", "cube-diffuse.jpg", "scenes/default.ron"] {
+        assert_eq!(pack.read(name).unwrap().unwrap(), std::fs::read(res().join(name)).unwrap(), "{}", name);
+    }
+    assert_eq!(pack.read("notes.txt").unwrap().unwrap(), b"text ".repeat(100));
+    assert_eq!(pack.read("missing.txt").unwrap(), None);
+
+    // Text shrinks, while noise wouldn't and is stored as is
+    let obj = pack.entry("cube.obj").unwrap();
+    assert_eq!(obj.compression, Compression::Deflate);
+    assert!(obj.stored_size() < obj.size);
+    let noise_entry = pack.entry("noise.bin").unwrap();
+    assert_eq!(noise_entry.compression, Compression::Store);
+    assert_eq!(noise_entry.stored_size(), noise_entry.size);
+    assert_eq!(pack.read("noise.bin").unwrap().unwrap(), noise(4096));
+}
+
+#[test]
+fn corrupt_entries_fail_their_checksum() {
+    let dir = temp_dir("corrupt");
+    let path = dir.join("corrupt.pack");
+    let mut builder = PackBuilder::new(Compression::Store);
+    builder.add("a.txt", b"first entry".to_vec()).unwrap();
+    builder.add("b.txt", b"second entry".to_vec()).unwrap();
+    builder.write(&path).unwrap();
+
+    let mut bytes = std::fs::read(&path).unwrap();
+    let at = bytes.windows(5).position(|window| window == b"first").unwrap();
+    bytes[at] ^= 0xff;
+    std::fs::write(&path, &bytes).unwrap();
+
+    let pack = Pack::open(&path).unwrap();
+    let error = format!("{:?}", pack.read("a.txt").unwrap_err());
+    assert!(error.contains("checksum"), "{}", error);
+    assert_eq!(pack.read("b.txt").unwrap().unwrap(), b"second entry");
+
+    std::fs::write(&path, b"not a pack at all").unwrap();
+    assert!(Pack::open(&path).is_err());
+}
+
+#[test]
+fn higher_priority_mounts_override_lower_ones() {
+    let dir = temp_dir("vfs");
+    let loose = dir.join("loose");
+    std::fs::create_dir_all(&loose).unwrap();
+    std::fs::write(loose.join("cube.mtl"), "loose").unwrap();
+    let path = dir.join("patch.pack");
+    let mut builder = PackBuilder::new(Compression::Deflate);
+    builder.add("cube.mtl", b"packed".to_vec()).unwrap();
+    builder.add("only-packed.txt", b"packed".to_vec()).unwrap();
+    builder.write(&path).unwrap();
+
+    let mut vfs = Vfs::new();
+    vfs.mount_dir(res(), 0);
+    vfs.mount_pack(&path, 1).unwrap();
+    assert_eq!(vfs.read("cube.mtl").unwrap(), b"packed");
+    assert_eq!(vfs.read("only-packed.txt").unwrap(), b"packed");
+    assert_eq!(vfs.read("cube.obj").unwrap(), std::fs::read(res().join("cube.obj")).unwrap());
+
+    vfs.mount_dir(&loose, 2);
+    assert_eq!(vfs.read("cube.mtl").unwrap(), b"loose");
+    // Same priority: the earlier mount wins
+    vfs.mount_dir(res(), 2);
+    assert_eq!(vfs.read("cube.mtl").unwrap(), b"loose");
+    assert_eq!(vfs.mounts().map(|(priority, _)| priority).collect::<Vec<_>>(), [2, 2, 1, 0]);
+
+    assert!(vfs.contains("scenes/../cube.obj"));
+    assert!(!vfs.contains("missing.txt"));
+    assert!(vfs.read("missing.txt").is_err());
+}
@@ -0,0 +1,69 @@
+//! Ray casting against instances, which needs no GPU or window.
+
+mod common;
+
+use cgmath::prelude::*;
+use common::{camera, instance, unit_cube};
+use wgpu_learn_01::picking::{pick_instance, Ray};
+use wgpu_learn_01::{Instance, Projection};
+
+#[test]
+fn ray_hits_and_misses_box() {
+    let bounds = unit_cube();
+    let hit = Ray::new((0.0, 0.0, 5.0).into(), -cgmath::Vector3::unit_z()).intersect_aabb(&bounds);
+    assert_eq!(hit, Some(4.0));
+
+    let inside = Ray::new((0.0, 0.0, 0.0).into(), cgmath::Vector3::unit_x()).intersect_aabb(&bounds);
+    assert_eq!(inside, Some(0.0));
+
+    let behind = Ray::new((0.0, 0.0, 5.0).into(), cgmath::Vector3::unit_z()).intersect_aabb(&bounds);
+    assert_eq!(behind, None);
+
+    let beside = Ray::new((0.0, 1.5, 5.0).into(), -cgmath::Vector3::unit_z()).intersect_aabb(&bounds);
+    assert_eq!(beside, None);
+}
+
+#[test]
+fn screen_center_ray_follows_view_direction() {
+    for (projection, reverse_z) in [
+        (Projection::perspective(cgmath::Deg(45.0), 0.1, 100.0), false),
+        (Projection::perspective(cgmath::Deg(45.0), 0.1, 100.0), true),
+        (Projection::orthographic(10.0, 0.1, 100.0), false),
+        (Projection::infinite_perspective(cgmath::Deg(45.0), 0.1), true),
+    ] {
+        let ray = camera(2.0, projection, reverse_z).screen_ray(100.0, 50.0, 200.0, 100.0);
+        assert!((ray.direction - -cgmath::Vector3::unit_z()).magnitude() < 1e-4, "{:?}", projection);
+        assert!((ray.origin.x).abs() < 1e-4 && (ray.origin.y).abs() < 1e-4, "{:?}", projection);
+    }
+}
+
+#[test]
+fn picks_closest_instance() {
+    let instances = vec![instance(0.0, 0.0, -6.0), instance(0.0, 0.0, 0.0), instance(4.0, 0.0, 0.0)];
+    let ray = camera(2.0, Projection::perspective(cgmath::Deg(45.0), 0.1, 100.0), false)
+        .screen_ray(100.0, 50.0, 200.0, 100.0);
+    let hit = pick_instance(&ray, &instances, &[Some(unit_cube())]).unwrap();
+    assert_eq!(hit.instance, 1);
+    // Enters the middle cube through its front face
+    assert!((ray.at(hit.distance).z - 1.0).abs() < 1e-3);
+
+    let ray = Ray::new((4.0, 0.0, 10.0).into(), -cgmath::Vector3::unit_z());
+    assert_eq!(pick_instance(&ray, &instances, &[Some(unit_cube())]).unwrap().instance, 2);
+
+    let ray = Ray::new((10.0, 0.0, 10.0).into(), -cgmath::Vector3::unit_z());
+    assert_eq!(pick_instance(&ray, &instances, &[Some(unit_cube())]), None);
+}
+
+#[test]
+fn boxes_turn_with_their_instance() {
+    // Turned 45 degrees the cube's corner reaches out to sqrt(2), so a ray
+    // that would pass beside the unturned cube hits the turned one.
+    let ray = Ray::new((1.3, 0.0, 10.0).into(), -cgmath::Vector3::unit_z());
+    assert_eq!(pick_instance(&ray, &[instance(0.0, 0.0, 0.0)], &[Some(unit_cube())]), None);
+    let turned = Instance {
+        rotation: cgmath::Quaternion::from_angle_y(cgmath::Deg(45.0)),
+        ..instance(0.0, 0.0, 0.0)
+    };
+    let hit = pick_instance(&ray, &[turned], &[Some(unit_cube())]).unwrap();
+    assert!((hit.distance - (10.0 - (2.0f32.sqrt() - 1.3))).abs() < 1e-3);
+}
@@ -0,0 +1,40 @@
+//! Picking through the ID buffer, on a headless renderer.
+
+use wgpu_learn_01::HeadlessState;
+
+const SIZE: u32 = 64;
+
+#[test]
+fn pixels_pick_the_instance_drawn_there() {
+    let mut state = match pollster::block_on(HeadlessState::new(SIZE, SIZE)) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("skipping pixel picking test: {}", e);
+            return;
+        }
+    };
+    // Looking down on the default scene's grid of cubes, with one in the
+    // middle and background above the grid
+    let renderer = state.renderer_mut();
+    renderer.camera.eye = (0.0, 20.0, 25.0).into();
+    renderer.camera.target = (0.0, 0.0, 0.0).into();
+    state.render().unwrap();
+
+    let center = SIZE / 2;
+    let expected = state.renderer().pick(center as f32 + 0.5, center as f32 + 0.5).unwrap();
+    let hit = state.renderer_mut().pick_pixel(center, center).unwrap().unwrap();
+    assert_eq!(hit.instance, expected.instance);
+    assert_eq!(hit.mesh, 0);
+    assert!(hit.depth > 0.0 && hit.depth < 1.0, "{:?}", hit);
+
+    // A material override on the first instance splits the draws, so the
+    // middle one comes from a run starting further into the instance buffer
+    state.renderer_mut().instances.get_mut(0).unwrap().material = Some(0);
+    state.render().unwrap();
+    let split = state.renderer_mut().pick_pixel(center, center).unwrap().unwrap();
+    assert_eq!(split.instance, expected.instance);
+    assert_eq!(split.mesh, 0);
+
+    assert_eq!(state.renderer_mut().pick_pixel(0, 0).unwrap(), None);
+    assert_eq!(state.renderer_mut().pick_pixel(SIZE, 0).unwrap(), None);
+}
@@ -0,0 +1,85 @@
+//! Projection matrices, which need no GPU or window.
+
+use cgmath::prelude::*;
+use wgpu_learn_01::Projection;
+
+const ASPECT: f32 = 2.0;
+
+// Normalized device coordinates of a view space point
+fn project(projection: &Projection, reverse_z: bool, point: [f32; 3]) -> cgmath::Vector3<f32> {
+    let clip = projection.matrix(ASPECT, reverse_z) * cgmath::Point3::from(point).to_homogeneous();
+    clip.truncate() / clip.w
+}
+
+fn assert_close(actual: f32, expected: f32) {
+    assert!((actual - expected).abs() < 1e-4, "{} != {}", actual, expected);
+}
+
+// Depth at the near and far plane, looking down -z
+fn assert_depths(projection: &Projection, far: f32, near_depth: f32, far_depth: f32) {
+    let reverse_z = near_depth > far_depth;
+    assert_close(project(projection, reverse_z, [0.0, 0.0, -projection.znear()]).z, near_depth);
+    assert_close(project(projection, reverse_z, [0.0, 0.0, -far]).z, far_depth);
+}
+
+#[test]
+fn depth_runs_from_near_to_far() {
+    let projections = [
+        Projection::perspective(cgmath::Deg(45.0), 0.1, 100.0),
+        Projection::orthographic(10.0, 0.1, 100.0),
+        Projection::off_center(cgmath::Deg(45.0), cgmath::Vector2::new(0.5, -0.25), 0.1, 100.0),
+    ];
+    for projection in &projections {
+        assert_depths(projection, 100.0, 0.0, 1.0);
+        assert_depths(projection, 100.0, 1.0, 0.0);
+    }
+
+    // Without a far plane depth only gets close to the limit
+    let infinite = Projection::infinite_perspective(cgmath::Deg(45.0), 0.1);
+    assert_eq!(infinite.zfar(), f32::INFINITY);
+    assert_depths(&infinite, 1.0e6, 0.0, 1.0);
+    assert_depths(&infinite, 1.0e6, 1.0, 0.0);
+    // Reversed, depth is exactly znear / distance
+    assert_close(project(&infinite, true, [0.0, 0.0, -10.0]).z, 0.01);
+}
+
+#[test]
+fn the_image_spans_the_field_of_view() {
+    // 90 degrees vertically: the top edge is as far up as the point is away
+    let perspective = Projection::perspective(cgmath::Deg(90.0), 0.1, 100.0);
+    let corner = project(&perspective, false, [2.0 * ASPECT, 2.0, -2.0]);
+    assert_close(corner.x, 1.0);
+    assert_close(corner.y, 1.0);
+
+    let orthographic = Projection::orthographic(10.0, 0.1, 100.0);
+    for z in [-1.0, -50.0] {
+        let corner = project(&orthographic, false, [5.0 * ASPECT, -5.0, z]);
+        assert_close(corner.x, 1.0);
+        assert_close(corner.y, -1.0);
+    }
+}
+
+#[test]
+fn off_center_shifts_the_view_axis() {
+    let on_axis = |shift: cgmath::Vector2<f32>| {
+        let projection = Projection::off_center(cgmath::Deg(90.0), shift, 0.1, 100.0);
+        let center = project(&projection, false, [0.0, 0.0, -5.0]);
+        (center.x, center.y)
+    };
+    let (x, y) = on_axis(cgmath::Vector2::zero());
+    assert_close(x, 0.0);
+    assert_close(y, 0.0);
+    let (x, y) = on_axis(cgmath::Vector2::new(1.0, 0.0));
+    assert_close(x, -1.0);
+    assert_close(y, 0.0);
+    let (x, y) = on_axis(cgmath::Vector2::new(0.0, -0.5));
+    assert_close(x, 0.0);
+    assert_close(y, 0.5);
+
+    // Unshifted it matches the plain perspective
+    let plain = Projection::perspective(cgmath::Deg(90.0), 0.1, 100.0);
+    let unshifted = Projection::off_center(cgmath::Deg(90.0), cgmath::Vector2::zero(), 0.1, 100.0);
+    let point = [1.0, -2.0, -7.0];
+    let (a, b) = (project(&plain, false, point), project(&unshifted, false, point));
+    assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+}
@@ -0,0 +1,107 @@
+//! Scene files. Only the last test needs a GPU.
+
+use wgpu_learn_01::{HeadlessState, Scene};
+
+// One camera, at `eye_z` along the z axis
+fn camera(eye_z: f32) -> String {
+    format!(
+        "(eye: (x: 0.0, y: 1.0, z: {:?}), target: (x: 0.0, y: 0.0, z: 0.0), up: (x: 0.0, y: 1.0, z: 0.0), \
+         projection: Perspective(fovy: (45.0), znear: 0.1, zfar: 100.0), reverse_z: false)",
+        eye_z
+    )
+}
+
+#[test]
+fn default_scene_survives_a_roundtrip() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/res/scenes/default.ron");
+    let scene = Scene::load(path).unwrap();
+    assert_eq!(scene.models, ["cube.obj"]);
+    assert_eq!(scene.instances.len(), 100);
+    assert_eq!(scene.lights.len(), 2);
+
+    let again = Scene::from_ron(&scene.to_ron().unwrap()).unwrap();
+    assert_eq!(again, scene);
+}
+
+#[test]
+fn missing_fields_take_defaults() {
+    let scene = Scene::from_ron(
+        r#"(
+            models: ["cube.obj"],
+            instances: [(position: (x: 1.0, y: 2.0, z: 3.0))],
+            camera: (
+                eye: (x: 0.0, y: 1.0, z: 2.0),
+                target: (x: 0.0, y: 0.0, z: 0.0),
+                up: (x: 0.0, y: 1.0, z: 0.0),
+                projection: Perspective(fovy: (45.0), znear: 0.1, zfar: 100.0),
+                reverse_z: false,
+            ),
+        )"#,
+    )
+    .unwrap();
+    assert_eq!((scene.cameras.len(), scene.active_camera), (1, 0));
+    assert!(scene.lights.is_empty());
+    assert_eq!(scene.clear_color, [0.0, 0.0, 0.0, 1.0]);
+
+    let instance = scene.instances[0];
+    assert_eq!(instance.position, (1.0, 2.0, 3.0).into());
+    assert_eq!(instance.scale, (1.0, 1.0, 1.0).into());
+    assert_eq!(instance.color, [1.0; 4]);
+    assert_eq!(instance.model, 0);
+}
+
+#[test]
+fn scenes_hold_several_cameras() {
+    let scene = Scene::from_ron(&format!(
+        "(models: [], cameras: [{}, {}], active_camera: 1)",
+        camera(2.0),
+        camera(8.0)
+    ))
+    .unwrap();
+    assert_eq!(scene.cameras.len(), 2);
+    assert_eq!(scene.camera().unwrap().eye.z, 8.0);
+    assert_eq!(Scene::from_ron(&scene.to_ron().unwrap()).unwrap(), scene);
+
+    let invalid = [
+        "(models: [])".to_string(),
+        "(models: [], cameras: [])".to_string(),
+        format!("(models: [], cameras: [{}], active_camera: 1)", camera(2.0)),
+        format!("(models: [], camera: {}, cameras: [{}])", camera(2.0), camera(8.0)),
+    ];
+    for text in &invalid {
+        assert!(Scene::from_ron(text).is_err(), "{}", text);
+    }
+}
+
+#[test]
+fn switching_cameras_keeps_their_changes() {
+    let mut state = match pollster::block_on(HeadlessState::new(64, 32)) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("skipping camera switching test: {}", e);
+            return;
+        }
+    };
+    let renderer = state.renderer_mut();
+    let mut scene = renderer.scene();
+    scene.cameras = Scene::from_ron(&format!("(models: [], cameras: [{}, {}])", camera(2.0), camera(8.0)))
+        .unwrap()
+        .cameras;
+    pollster::block_on(renderer.load_scene(&scene)).unwrap();
+    assert_eq!((renderer.active_camera(), renderer.camera.eye.z), (0, 2.0));
+    // The render target's aspect ratio stays
+    assert_eq!(renderer.camera.aspect, 2.0);
+
+    renderer.camera.eye.y = 5.0;
+    renderer.switch_camera(1).unwrap();
+    assert_eq!((renderer.camera.eye.z, renderer.camera.aspect), (8.0, 2.0));
+    assert!(renderer.switch_camera(2).is_err());
+    assert_eq!(renderer.scene().cameras[0].eye.y, 5.0);
+    assert_eq!(renderer.scene().active_camera, 1);
+
+    renderer.switch_camera(0).unwrap();
+    assert_eq!(renderer.camera.eye, (0.0, 5.0, 2.0).into());
+
+    scene.active_camera = 2;
+    assert!(pollster::block_on(renderer.load_scene(&scene)).is_err());
+}
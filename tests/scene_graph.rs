@@ -0,0 +1,169 @@
+//! Scene graph transform propagation and the instances it keeps in sync.
+//! Only the last test needs a GPU.
+
+use cgmath::prelude::*;
+use wgpu_learn_01::scene_graph::{ModelAttachment, SceneGraph, Transform};
+use wgpu_learn_01::{HeadlessState, Instance};
+
+fn at(x: f32, y: f32, z: f32) -> Transform {
+    Transform::from_translation(cgmath::Vector3::new(x, y, z))
+}
+
+fn position(graph: &SceneGraph, id: wgpu_learn_01::scene_graph::NodeId) -> cgmath::Vector3<f32> {
+    graph.world(id).unwrap().w.truncate()
+}
+
+#[test]
+fn children_follow_their_parents() {
+    let mut graph = SceneGraph::new();
+    let root = graph.add(None, at(1.0, 0.0, 0.0));
+    let child = graph.add(Some(root), at(0.0, 2.0, 0.0));
+    let grandchild = graph.add(Some(child), at(0.0, 0.0, 3.0));
+    graph.update_world();
+    assert_eq!(position(&graph, grandchild), cgmath::Vector3::new(1.0, 2.0, 3.0));
+
+    // Turning the root a quarter around y swings the children with it
+    graph.local_mut(root).unwrap().rotation = cgmath::Quaternion::from_angle_y(cgmath::Deg(90.0));
+    graph.update_world();
+    let moved = position(&graph, grandchild);
+    assert!((moved - cgmath::Vector3::new(4.0, 2.0, 0.0)).magnitude() < 1e-5, "{:?}", moved);
+
+    // Scale reaches the children's offsets too
+    graph.set_local(root, Transform { scale: cgmath::Vector3::new(2.0, 2.0, 2.0), ..at(0.0, 0.0, 0.0) });
+    graph.update_world();
+    assert_eq!(position(&graph, grandchild), cgmath::Vector3::new(0.0, 4.0, 6.0));
+}
+
+#[test]
+fn reparenting_keeps_the_local_transform() {
+    let mut graph = SceneGraph::new();
+    let a = graph.add(None, at(1.0, 0.0, 0.0));
+    let b = graph.add(None, at(0.0, 5.0, 0.0));
+    let child = graph.add(Some(a), at(0.0, 0.0, 1.0));
+    graph.update_world();
+
+    graph.set_parent(child, Some(b)).unwrap();
+    graph.update_world();
+    assert_eq!(graph.parent(child), Some(b));
+    assert!(graph.children(a).is_empty());
+    assert_eq!(graph.children(b), [child]);
+    assert_eq!(position(&graph, child), cgmath::Vector3::new(0.0, 5.0, 1.0));
+
+    assert!(graph.set_parent(b, Some(child)).is_err());
+    assert!(graph.set_parent(b, Some(b)).is_err());
+
+    graph.set_parent(child, None).unwrap();
+    graph.update_world();
+    assert_eq!(graph.roots(), [a, b, child]);
+    assert_eq!(position(&graph, child), cgmath::Vector3::new(0.0, 0.0, 1.0));
+}
+
+#[test]
+fn removing_a_node_removes_its_subtree() {
+    let mut graph = SceneGraph::new();
+    let root = graph.add(None, at(0.0, 0.0, 0.0));
+    let child = graph.add(Some(root), at(1.0, 0.0, 0.0));
+    let grandchild = graph.add(Some(child), at(1.0, 0.0, 0.0));
+    let other = graph.add(Some(root), at(0.0, 1.0, 0.0));
+
+    graph.remove(child);
+    assert!(!graph.contains(child));
+    assert!(!graph.contains(grandchild));
+    assert_eq!(graph.children(root), [other]);
+
+    // The freed slot gets reused without reviving the old handle
+    let reused = graph.add(None, at(0.0, 0.0, 0.0));
+    assert_ne!(reused, child);
+    assert_ne!(reused, grandchild);
+    assert!(!graph.contains(child) && !graph.contains(grandchild));
+
+    graph.clear();
+    assert!(graph.roots().is_empty());
+    assert!(!graph.contains(root) && !graph.contains(reused));
+}
+
+#[test]
+fn reparenting_moves_the_whole_subtree() {
+    let mut graph = SceneGraph::new();
+    let a = graph.add(None, at(1.0, 0.0, 0.0));
+    let b = graph.add(None, at(0.0, 5.0, 0.0));
+    let child = graph.add(Some(a), at(0.0, 0.0, 1.0));
+    let grandchild = graph.add(Some(child), at(0.0, 0.0, 1.0));
+    graph.update_world();
+    assert_eq!(position(&graph, grandchild), cgmath::Vector3::new(1.0, 0.0, 2.0));
+
+    // Only the moved node is flagged, its children have to follow anyway
+    graph.set_parent(child, Some(b)).unwrap();
+    graph.update_world();
+    assert_eq!(position(&graph, grandchild), cgmath::Vector3::new(0.0, 5.0, 2.0));
+
+    // Invalid nodes fail and leave the graph alone
+    let removed = graph.add(None, at(0.0, 0.0, 0.0));
+    graph.remove(removed);
+    assert!(graph.set_parent(child, Some(removed)).is_err());
+    assert!(graph.set_parent(removed, Some(a)).is_err());
+    assert!(graph.set_parent(b, Some(grandchild)).is_err());
+    assert_eq!(graph.parent(child), Some(b));
+    assert_eq!(graph.roots(), [a, b]);
+    assert!(graph.local_mut(removed).is_none());
+}
+
+fn x_of(instances: &[Instance]) -> Vec<f32> {
+    instances.iter().map(|instance| instance.position.x).collect()
+}
+
+#[test]
+fn update_keeps_the_instance_list_in_sync() {
+    let mut state = match pollster::block_on(HeadlessState::new(64, 64)) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("skipping scene graph instance test: {}", e);
+            return;
+        }
+    };
+    let instances = &mut state.renderer_mut().instances;
+    instances.clear();
+    let mut graph = SceneGraph::new();
+    let root = graph.add(None, at(0.0, 1.0, 0.0));
+    let nodes = [1.0, 2.0, 3.0].map(|x| graph.add(Some(root), at(x, 0.0, 0.0)));
+    let grandchild = graph.add(Some(nodes[0]), at(10.0, 0.0, 0.0));
+    for (model, &node) in nodes.iter().chain([&grandchild]).enumerate() {
+        graph.attach(node, ModelAttachment { color: [0.5; 4], ..ModelAttachment::new(model) });
+    }
+    // Nodes without a model don't get an instance
+    assert_eq!(graph.attachment(root), None);
+    graph.update(instances);
+    assert_eq!(instances.len(), 4);
+    assert_eq!(graph.instance(root), None);
+    for node in nodes.iter().chain([&grandchild]) {
+        let instance = instances.get(graph.instance(*node).unwrap()).unwrap();
+        assert_eq!(instance.position, graph.world(*node).unwrap().w.truncate());
+        assert_eq!(instance.color, [0.5; 4]);
+    }
+
+    // Detaching takes the instance out and moves the later ones down
+    let detached = graph.detach(nodes[1]);
+    assert_eq!(detached.map(|attachment| attachment.model), Some(1));
+    assert_eq!(graph.attachment(nodes[1]), None);
+    graph.update(instances);
+    assert_eq!(x_of(instances.as_slice()), [1.0, 3.0, 11.0]);
+    assert_eq!(graph.instance(nodes[2]), Some(1));
+    assert_eq!(graph.instance(grandchild), Some(2));
+
+    // Removing a node takes its subtree's instances with it
+    graph.remove(nodes[0]);
+    graph.update(instances);
+    assert_eq!(x_of(instances.as_slice()), [3.0]);
+    assert_eq!(graph.instance(nodes[2]), Some(0));
+
+    // Attaching again and moving a parent rewrites the instances below it
+    graph.attach(nodes[1], ModelAttachment::new(1));
+    graph.update(instances);
+    assert_eq!(x_of(instances.as_slice()), [3.0, 2.0]);
+    graph.set_parent(nodes[2], Some(nodes[1])).unwrap();
+    graph.update(instances);
+    assert_eq!(x_of(instances.as_slice()), [5.0, 2.0]);
+    graph.local_mut(root).unwrap().translation.x = 100.0;
+    graph.update(instances);
+    assert_eq!(x_of(instances.as_slice()), [105.0, 102.0]);
+}
@@ -0,0 +1,78 @@
+//! Frame timing driven by hand, which needs no GPU or window.
+
+use std::time::{Duration, Instant};
+
+use wgpu_learn_01::timing::{FrameClock, Timestep};
+
+fn ms(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+}
+
+// A clock that has just ticked at the returned instant, with nothing to hand
+// out yet
+fn clock(timestep: Timestep) -> (FrameClock, Instant) {
+    let mut clock = FrameClock::new(timestep);
+    let start = Instant::now();
+    clock.tick_at(start);
+    // Drops the moment between creating the clock and the tick
+    clock.set_timestep(timestep);
+    (clock, start)
+}
+
+#[test]
+fn fixed_steps_carry_leftover_time_over() {
+    let (mut clock, start) = clock(Timestep::Fixed(ms(10)));
+    assert_eq!(clock.tick_at(start + ms(25)), ms(25));
+    assert_eq!(clock.steps().collect::<Vec<_>>(), [ms(10), ms(10)]);
+    assert!((clock.interpolation() - 0.5).abs() < 1e-6);
+
+    // Too short for a step on its own, but together with the leftover
+    assert_eq!(clock.tick_at(start + ms(29)), ms(4));
+    assert_eq!(clock.steps().count(), 0);
+    assert!((clock.interpolation() - 0.9).abs() < 1e-6);
+    clock.tick_at(start + ms(31));
+    assert_eq!(clock.steps().collect::<Vec<_>>(), [ms(10)]);
+    assert!((clock.interpolation() - 0.1).abs() < 1e-6);
+    assert_eq!(clock.frame_count(), 4);
+
+    // Switching the timestep drops the leftover
+    clock.set_timestep(Timestep::Fixed(ms(5)));
+    assert_eq!(clock.interpolation(), 0.0);
+}
+
+#[test]
+fn long_frames_are_cut_to_max_delta() {
+    let (mut clock, start) = clock(Timestep::Fixed(ms(10)));
+    assert_eq!(clock.tick_at(start + Duration::from_secs(5)), ms(250));
+    assert_eq!(clock.delta(), ms(250));
+    assert_eq!(clock.steps().count(), 25);
+
+    clock.set_max_delta(ms(50));
+    assert_eq!(clock.tick_at(start + Duration::from_secs(6)), ms(50));
+    assert_eq!(clock.steps().count(), 5);
+
+    // Time running backwards counts as no time
+    assert_eq!(clock.tick_at(start), Duration::ZERO);
+    assert_eq!(clock.steps().count(), 0);
+}
+
+#[test]
+fn variable_steps_cover_the_whole_frame() {
+    let (mut clock, start) = clock(Timestep::Variable);
+    assert_eq!(clock.tick_at(start + ms(16)), ms(16));
+    assert_eq!(clock.steps().collect::<Vec<_>>(), [ms(16)]);
+    assert!((clock.fps() - 62.5).abs() < 1e-3);
+    assert_eq!(clock.interpolation(), 0.0);
+
+    // A frame taking no time runs no update
+    clock.tick_at(start + ms(16));
+    assert_eq!(clock.steps().count(), 0);
+    assert_eq!(clock.fps(), 0.0);
+    assert!(clock.elapsed() >= ms(16));
+}
+
+#[test]
+#[should_panic(expected = "fixed timestep")]
+fn zero_fixed_steps_are_rejected() {
+    FrameClock::new(Timestep::Fixed(Duration::ZERO));
+}